@@ -1,4 +1,3 @@
-
 extern crate rustc_serialize;
 extern crate openssl;
 extern crate dotenv;
@@ -8,49 +7,233 @@ extern crate example_application;
 
 use std::path::Path;
 use dotenv::dotenv;
-use std::fs::File;
+use std::fs::{self, OpenOptions};
 use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
 use std::env;
-use example_application::oauth::crypto::{self, Encodable};
+use example_application::oauth::crypto::{self, Algorithm, Encodable, KeySpec};
+use example_application::oauth::crypto::keystore::{self, Manifest};
+use example_application::oauth::crypto::jwe::{self, KeyUnlock};
+use example_application::oauth::crypto::aead;
 
-// Using a path from the config generates JWKs
+// Usage:
+//
+//   generate_keys [options]          write a key pair named by kid to KEYSTORE_PATH
+//   generate_keys init [options]     create a keystore in KEYSTORE_PATH
+//   generate_keys rotate [options]   run one rotation step on KEYSTORE_PATH
+//   generate_keys thumbprint <path>  print the RFC 7638 thumbprint of a JWK
+//   generate_keys encrypt <path>     encrypt a secret key file in place
+//   generate_keys decrypt <path>     decrypt a secret key file in place
+//   generate_keys kek <path>         write a new key encryption key
+//   generate_keys session-key        print a new key for SESSION_KEYS
+//
+// Secret keys are written encrypted whenever SECRET_KEY_PASSPHRASE,
+// SECRET_KEY_PASSPHRASE_FILE or SECRET_KEY_KEK_FILE is set.
+//
+// Options:
+//
+//   --alg RS256|ES256|EdDSA  algorithm of generated keys (default RS256)
+//   --bits 2048|3072|4096    RSA key size (default 2048)
 fn main() {
     dotenv().ok();
 
-    let public_key_path: String = env::var("PUBLIC_KEY_PATH")
-        .expect("PUBLIC_KEY_PATH required");
-    let secret_key_path: String = env::var("SECRET_KEY_PATH")
-        .expect("SECRET_KEY_PATH required");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = args.first()
+        .and_then(|arg| if arg.starts_with("--") { None } else { Some(arg.clone()) });
 
-    let public_key_path = Path::new(public_key_path.as_str());
-    let secret_key_path = Path::new(secret_key_path.as_str());
+    match command {
+        None => generate_single_key(key_spec(&args)),
+        Some(ref command) if command == "init" => init_keystore(key_spec(&args)),
+        Some(ref command) if command == "rotate" => rotate_keystore(key_spec(&args)),
+        Some(ref command) if command == "thumbprint" => print_thumbprint(),
+        Some(ref command) if command == "encrypt" => encrypt_secret_key(),
+        Some(ref command) if command == "decrypt" => decrypt_secret_key(),
+        Some(ref command) if command == "kek" => write_kek(),
+        Some(ref command) if command == "session-key" => print_session_key(),
+        Some(command) => {
+            println!("Unknown command {}, expected init, rotate, thumbprint, encrypt, decrypt, kek or session-key", command);
+            std::process::exit(1);
+        },
+    }
+}
 
-    println!("Running generate_keys ...");
-    println!("PUBLIC_KEY_PATH = {:?}", public_key_path.clone());
-    println!("SECRET_KEY_PATH = {:?}", secret_key_path.clone());
+fn option(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
 
-    if secret_key_path.exists() {
-        println!("A secret key has already been generated for this service.")
-    } else {
-        let (secret_key, public_key) = crypto::generate_keypair().unwrap();
+fn key_spec(args: &[String]) -> KeySpec {
+    let algorithm = option(args, "--alg")
+        .map(|alg| Algorithm::from_name(&alg).expect("--alg must be RS256, ES256 or EdDSA"))
+        .unwrap_or(Algorithm::RS256);
+    let bits: u32 = option(args, "--bits")
+        .map(|bits| bits.parse().expect("--bits must be a number"))
+        .unwrap_or(2048);
 
-        let secret_key_json = secret_key.encode().unwrap();
-        let public_key_json = public_key.encode().unwrap();
+    KeySpec::from_algorithm(algorithm, bits)
+        .expect("--bits must be 2048, 3072 or 4096")
+}
 
-        let mut secret_key_file = File::create(secret_key_path)
-            .expect("Unable to create secret_key file");
-        let mut public_key_file = File::create(public_key_path)
-            .expect("Unable to create public_key file");
+fn unlock() -> Option<KeyUnlock> {
+    KeyUnlock::from_env()
+        .expect("Unable to read secret key passphrase or KEK")
+}
 
-        secret_key_file.write_all(secret_key_json.as_bytes())
-            .expect("Unable to write JSON data to secret key file");
-        public_key_file.write_all(public_key_json.as_bytes())
-            .expect("Unable to write JSON data to public_key file");
+fn required_unlock() -> KeyUnlock {
+    unlock()
+        .expect("SECRET_KEY_PASSPHRASE, SECRET_KEY_PASSPHRASE_FILE or SECRET_KEY_KEK_FILE required")
+}
 
-        println!("Generated secret key = {}", secret_key_json);
-        println!("Generated public key = {}", public_key_json);
+fn path_argument(command: &str) -> String {
+    env::args().nth(2)
+        .expect(&format!("Usage: generate_keys {} <path>", command))
+}
 
-        println!("Success");
+// Writes to a temporary file first and renames it over path, so an
+// interrupted rewrite never leaves a half written key behind. Everything
+// written here is secret, so new files are readable by their owner only.
+fn write_file(path: &Path, contents: &str) {
+    let tmp_path = path.with_extension("tmp");
+    let _ = fs::remove_file(&tmp_path);
+    {
+        let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp_path)
+            .expect("Unable to create file");
+        file.write_all(contents.as_bytes())
+            .expect("Unable to write file");
+        file.sync_all()
+            .expect("Unable to write file");
+    }
+    // Keep the permissions of the file being replaced
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(&tmp_path, metadata.permissions())
+            .expect("Unable to copy file permissions");
     }
+    fs::rename(&tmp_path, path)
+        .expect("Unable to replace file");
+}
+
+fn encrypt_secret_key() {
+    let path = path_argument("encrypt");
+    let unlock = required_unlock();
+
+    let secret_key = crypto::read_secret_key_file(Path::new(&path), None)
+        .expect("Unable to read plain secret key");
+    let encrypted = crypto::encode_secret_key(&secret_key, Some(&unlock))
+        .expect("Unable to encrypt secret key");
+    write_file(Path::new(&path), &encrypted);
+
+    println!("Encrypted {}", path);
+}
+
+fn decrypt_secret_key() {
+    let path = path_argument("decrypt");
+    let unlock = required_unlock();
+
+    let secret_key = crypto::read_secret_key_file(Path::new(&path), Some(&unlock))
+        .expect("Unable to decrypt secret key");
+    let json = secret_key.encode()
+        .expect("Unable to encode secret key");
+    write_file(Path::new(&path), &json);
+
+    println!("Decrypted {}", path);
+}
+
+fn write_kek() {
+    let path = path_argument("kek");
+    if Path::new(&path).exists() {
+        println!("A key encryption key already exists at {}", path);
+        std::process::exit(1);
+    }
+
+    let kek = jwe::generate_kek()
+        .expect("Unable to generate key encryption key");
+    write_file(Path::new(&path), &kek);
+
+    println!("Wrote key encryption key to {}", path);
+}
+
+// Prepend the output to SESSION_KEYS to rotate, and drop the last key once
+// sessions sealed with it have expired.
+fn print_session_key() {
+    let key = aead::generate_key()
+        .expect("Unable to generate session key");
+    println!("{}", key);
 }
 
+fn keystore_path() -> String {
+    env::var("KEYSTORE_PATH")
+        .expect("KEYSTORE_PATH required")
+}
+
+fn print_manifest(manifest: &Manifest) {
+    for entry in manifest.keys.iter() {
+        println!("{:?} {}", entry.status, entry.kid);
+    }
+}
+
+fn init_keystore(spec: KeySpec) {
+    let keystore_path = keystore_path();
+
+    println!("Running generate_keys init ...");
+    println!("KEYSTORE_PATH = {:?}", keystore_path);
+    println!("KEY_SPEC = {:?}", spec);
+
+    let manifest = keystore::init(Path::new(&keystore_path), spec, unlock().as_ref())
+        .expect("Unable to initialise keystore");
+    print_manifest(&manifest);
+
+    println!("Success");
+}
+
+fn rotate_keystore(spec: KeySpec) {
+    let keystore_path = keystore_path();
+    let grace_period: u64 = env::var("KEY_GRACE_PERIOD")
+        .map(|period| period.parse().expect("KEY_GRACE_PERIOD must be a number of seconds"))
+        .unwrap_or(86400);
+
+    println!("Running generate_keys rotate ...");
+    println!("KEYSTORE_PATH = {:?}", keystore_path);
+    println!("KEY_GRACE_PERIOD = {}", grace_period);
+    println!("KEY_SPEC = {:?}", spec);
+
+    let manifest = keystore::rotate(Path::new(&keystore_path), grace_period, spec, unlock().as_ref())
+        .expect("Unable to rotate keystore");
+    print_manifest(&manifest);
+
+    println!("Success");
+}
+
+fn print_thumbprint() {
+    let path = path_argument("thumbprint");
+    let thumbprint = crypto::thumbprint_file(Path::new(&path))
+        .expect("Unable to compute thumbprint");
+    println!("{}", thumbprint);
+}
+
+// Writes <kid>.public.json and <kid>.secret.json to KEYSTORE_PATH, for
+// PUBLIC_KEY_PATH / SECRET_KEY_PATH to point at. Unlike init and rotate
+// this leaves the manifest alone.
+fn generate_single_key(spec: KeySpec) {
+    let keystore_path = keystore_path();
+    let dir = Path::new(&keystore_path);
+
+    println!("Running generate_keys ...");
+    println!("KEYSTORE_PATH = {:?}", keystore_path);
+    println!("KEY_SPEC = {:?}", spec);
+
+    fs::create_dir_all(dir)
+        .expect("Unable to create keystore directory");
+
+    let (secret_key, public_key) = crypto::generate(spec)
+        .expect("Unable to generate key pair");
+    keystore::write_key_pair(dir, &secret_key, &public_key, unlock().as_ref())
+        .expect("Unable to write key pair");
+
+    println!("Generated kid = {}", public_key.kid());
+    println!("PUBLIC_KEY_PATH = {:?}", keystore::public_key_path(dir, public_key.kid()));
+    println!("SECRET_KEY_PATH = {:?}", keystore::secret_key_path(dir, public_key.kid()));
+
+    println!("Success");
+}
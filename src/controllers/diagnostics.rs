@@ -0,0 +1,130 @@
+use openssl::memcmp;
+use rocket::Outcome;
+use rocket::State;
+use rocket::http::Status;
+use rocket::request::{self, Request, FromRequest};
+use rocket_contrib::JSON;
+use oauth::settings::{Settings, FlowStoreKind};
+use oauth::crypto::keystore::KeyStore;
+use oauth::reload::Reloadable;
+use oauth::session::{SessionBackendKind, SessionStoreKind};
+
+//--------------------------------------------------------------------------
+// Diagnostics
+//--------------------------------------------------------------------------
+
+// What this instance is configured against, without any secrets
+#[derive(Serialize)]
+pub struct DiagnosticsResponse {
+    pub profile: String,
+    pub client_id: String,
+    pub public_client: bool,
+    pub redirect_uri: String,
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub discovery: bool,
+    pub cookie_domain: Option<String>,
+    pub cookie_path: String,
+    pub cookie_secure: bool,
+    pub cookie_same_site: Option<String>,
+    pub cookie_prefix: String,
+    pub flow_store: String,
+    pub session_backend: String,
+    pub session_store: String,
+    pub session_ttl: u64,
+    pub provider_timeout: u64,
+    pub provider_retries: u64,
+    pub signing_kid: String,
+    pub published_kids: Vec<String>,
+}
+
+impl DiagnosticsResponse {
+    pub fn new(settings: &Settings, keystore: &KeyStore) -> DiagnosticsResponse {
+        let flow_store = match settings.flow_store {
+            FlowStoreKind::Memory => "memory",
+            FlowStoreKind::File(_) => "file",
+        };
+        let session_backend = match settings.session_backend {
+            SessionBackendKind::Server => "server",
+            SessionBackendKind::Cookie => "cookie",
+        };
+        let session_store = match settings.session_store {
+            SessionStoreKind::Memory => "memory",
+            SessionStoreKind::File(_) => "file",
+        };
+
+        DiagnosticsResponse {
+            profile: settings.profile.name().to_string(),
+            client_id: settings.client_id.clone(),
+            public_client: settings.public_client,
+            redirect_uri: settings.redirect_uri.clone(),
+            issuer: settings.endpoints.issuer.clone(),
+            authorization_endpoint: settings.endpoints.authorization.clone(),
+            token_endpoint: settings.endpoints.token.clone(),
+            userinfo_endpoint: settings.endpoints.userinfo.clone(),
+            jwks_uri: settings.endpoints.jwks.clone(),
+            discovery: settings.discovery_issuer.is_some(),
+            cookie_domain: settings.cookie.domain.clone(),
+            cookie_path: settings.cookie.path.clone(),
+            cookie_secure: settings.cookie.secure,
+            cookie_same_site: settings.cookie.same_site.map(|same_site| same_site.name().to_string()),
+            cookie_prefix: settings.cookie.prefix.clone(),
+            flow_store: flow_store.to_string(),
+            session_backend: session_backend.to_string(),
+            session_store: session_store.to_string(),
+            session_ttl: settings.session_ttl,
+            provider_timeout: settings.provider_timeout,
+            provider_retries: settings.provider_retries,
+            signing_kid: keystore.signing_key().kid.clone(),
+            published_kids: keystore.published().iter().map(|key| key.kid().to_string()).collect(),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Diagnostics Access
+//--------------------------------------------------------------------------
+
+// A request presenting DIAGNOSTICS_TOKEN as "Authorization: Bearer <token>".
+// Fails with 403 otherwise, and always when no token is configured.
+pub struct DiagnosticsAccess;
+
+impl DiagnosticsAccess {
+
+    pub fn allows(settings: &Settings, authorization: Option<&str>) -> bool {
+        let expected = settings.diagnostics_token.expose().as_bytes();
+        let token = match authorization {
+            Some(value) if value.starts_with("Bearer ") => value["Bearer ".len()..].trim().as_bytes(),
+            _ => return false,
+        };
+        !expected.is_empty() && expected.len() == token.len() && memcmp::eq(expected, token)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for DiagnosticsAccess {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<DiagnosticsAccess, ()> {
+        let settings = match State::<Reloadable<Settings>>::from_request(request) {
+            Outcome::Success(settings) => settings.current(),
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        match DiagnosticsAccess::allows(&settings, request.headers().get_one("Authorization")) {
+            true => Outcome::Success(DiagnosticsAccess),
+            false => Outcome::Failure((Status::Forbidden, ())),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Routes
+//--------------------------------------------------------------------------
+
+#[get("/diagnostics")]
+pub fn diagnostics(_access: DiagnosticsAccess, settings: State<Reloadable<Settings>>, keystore: State<Reloadable<KeyStore>>) -> JSON<DiagnosticsResponse> {
+    JSON(DiagnosticsResponse::new(&settings.current(), &keystore.current()))
+}
@@ -1 +1,3 @@
 pub mod oauth;
+pub mod profile;
+pub mod diagnostics;
@@ -1,26 +1,64 @@
-
 use rocket::Outcome;
 use rocket::State;
-use rocket::http::{Status, CookieJar, Cookie};
-use rocket::request::{self, Request, FromRequest};
+use rocket::http::{Status, CookieJar};
+use rocket::http::uri::URI;
+use rocket::request::{self, Request, FromRequest, Form, FormItems};
 use rocket::response::{Redirect};
 use rocket_contrib::JSON;
-use reqwest;
-use reqwest::header::{Headers, Bearer, Authorization};
-use reqwest::StatusCode;
 use oauth::settings::Settings;
-use oauth::flow::Flow;
+use oauth::reload::Reloadable;
+use oauth::flow::{Flow, FlowCheck};
 use oauth::crypto::PublicKey;
+use oauth::crypto::keystore::KeyStore;
+use oauth::crypto::jwt::{self, SessionClaims};
+use oauth::id_token::IdTokenVerifier;
+use oauth::session::{Sessions, SessionData, CsrfCheck};
+use oauth::guard::AuthenticatedUser;
+use oauth::client::ProviderClient;
 use oauth::*;
-use serde_json;
-use error::OAuthError;
+use error::{OAuthError, ErrorFormat, ErrorResponse};
+
+//--------------------------------------------------------------------------
+// Flow Parameters
+//--------------------------------------------------------------------------
 
-fn build_cookie(name: String, value: String) -> Cookie<'static> {
-    Cookie::build(name, value)
-        .domain("www.example.com")
-        .path("/api")
-        .http_only(true)
-        .finish()
+fn decode_query_value(value: &str) -> String {
+    let value = value.replace("+", " ");
+    URI::percent_decode_lossy(value.as_bytes()).into_owned()
+}
+
+// Reads the optional `return_to` and `scope` query parameters along with the
+// Origin header, so the callback can finish the flow where it started.
+impl<'a, 'r> FromRequest<'a, 'r> for FlowParams {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<FlowParams, ()> {
+        let mut params = FlowParams::default();
+
+        if let Some(query) = request.uri().query() {
+            for (key, value) in FormItems::from(query) {
+                match key {
+                    "return_to" => params.return_to = Some(decode_query_value(value)),
+                    "scope" => {
+                        params.scopes = decode_query_value(value)
+                            .split(|c| c == ' ' || c == ',')
+                            .filter(|scope| !scope.is_empty())
+                            .map(|scope| scope.to_string())
+                            .collect();
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        let origins: Vec<_> = request.headers().get("Origin").collect();
+        if origins.len() > 1 {
+            return Outcome::Failure((Status::BadRequest, ()));
+        }
+        params.origin = origins.first().map(|origin| origin.to_string());
+
+        Outcome::Success(params)
+    }
 }
 
 //--------------------------------------------------------------------------
@@ -28,27 +66,30 @@ fn build_cookie(name: String, value: String) -> Cookie<'static> {
 //--------------------------------------------------------------------------
 
 #[get("/login")]
-pub fn login<'a>(settings: State<Settings>, flow: State<Flow>) -> JSON<OAuthResponse> {
-    let settings = settings.inner();
+pub fn login<'a>(params: FlowParams, settings: State<Reloadable<Settings>>, flow: State<Flow>, session: &CookieJar) -> Result<JSON<OAuthResponse>, OAuthError> {
+    let settings = settings.current();
     let flow = flow.inner();
-    let info = build_signin_response(settings, flow);
-    JSON(info)
+    let info = try!(build_signin_response(&settings, flow, params));
+    settings.cookie.add(session, "state", info.state());
+    Ok(JSON(info))
 }
 
 #[get("/signup")]
-pub fn signup<'a>(settings: State<Settings>, flow: State<'a, Flow>) -> JSON<OAuthResponse> {
-    let settings = settings.inner();
+pub fn signup<'a>(params: FlowParams, settings: State<Reloadable<Settings>>, flow: State<'a, Flow>, session: &CookieJar) -> Result<JSON<OAuthResponse>, OAuthError> {
+    let settings = settings.current();
     let flow = flow.inner();
-    let info = build_signup_response(settings, flow);
-    JSON(info)
+    let info = try!(build_signup_response(&settings, flow, params));
+    settings.cookie.add(session, "state", info.state());
+    Ok(JSON(info))
 }
 
 #[get("/best_choice")]
-pub fn best_choice<'a>(settings: State<Settings>, flow: State<'a, Flow>) -> JSON<OAuthResponse> {
-    let settings = settings.inner();
+pub fn best_choice<'a>(params: FlowParams, settings: State<Reloadable<Settings>>, flow: State<'a, Flow>, session: &CookieJar) -> Result<JSON<OAuthResponse>, OAuthError> {
+    let settings = settings.current();
     let flow = flow.inner();
-    let info = build_best_choice_response(settings, flow);
-    JSON(info)
+    let info = try!(build_best_choice_response(&settings, flow, params));
+    settings.cookie.add(session, "state", info.state());
+    Ok(JSON(info))
 }
 
 #[derive(Serialize, Deserialize, FromForm)]
@@ -57,149 +98,221 @@ pub struct ForceAuthRequest {
 }
 
 #[get("/force_auth?<req>")]
-pub fn force_auth<'a>(req: ForceAuthRequest, settings: State<'a, Settings>, flow: State<'a, Flow>) -> JSON<OAuthResponse> {
-    let settings = settings.inner();
+pub fn force_auth<'a>(req: ForceAuthRequest, params: FlowParams, settings: State<'a, Reloadable<Settings>>, flow: State<'a, Flow>, session: &CookieJar) -> Result<JSON<OAuthResponse>, OAuthError> {
+    let settings = settings.current();
     let flow = flow.inner();
-    let info = build_force_auth_response(&settings, flow, req.email);
-    JSON(info)
+    let info = try!(build_force_auth_response(&settings, flow, params, req.email));
+    settings.cookie.add(session, "state", info.state());
+    Ok(JSON(info))
 }
 
 //--------------------------------------------------------------------------
 // Authorization
 //--------------------------------------------------------------------------
 
-pub struct Referer(String);
+// The provider sends the browser here, so failed and cancelled logins are
+// rendered as a page unless the client asked for JSON.
+#[get("/oauth?<req>")]
+pub fn oauth(req: OAuthRequest, format: ErrorFormat, settings: State<Reloadable<Settings>>, flow: State<Flow>, keystore: State<Reloadable<KeyStore>>, id_tokens: State<Reloadable<IdTokenVerifier>>, client: State<Reloadable<ProviderClient>>, sessions: State<Sessions>, session: &CookieJar) -> Result<Redirect, ErrorResponse> {
+    let settings = settings.current();
+    let keystore = keystore.current();
+    let id_tokens = id_tokens.current();
+    let client = client.current();
+    finish_login(req, &settings, flow.inner(), &keystore, &id_tokens, &client, sessions.inner(), session)
+        .map_err(|err| err.respond_as(format))
+}
 
-impl<'a, 'r> FromRequest<'a, 'r> for Referer {
-    type Error = ();
+fn finish_login(req: OAuthRequest, settings: &Settings, flow: &Flow, keystore: &KeyStore, id_tokens: &IdTokenVerifier, client: &ProviderClient, sessions: &Sessions, session: &CookieJar) -> Result<Redirect, OAuthError> {
+    let state_cookie = settings.cookie.find(session, "state")
+        .unwrap_or(String::new());
 
-    fn from_request(request: &'a Request<'r>) -> request::Outcome<Referer, ()> {
-        let headers: Vec<_> = request.headers().get("Referer").collect();
-        if headers.len() != 1 {
-            return Outcome::Failure((Status::BadRequest, ()));
+    // The state (nonce) is consumed whatever the outcome so it can never be
+    // replayed, delete it from the session too.
+    let check = try!(flow.check(&req.state, &state_cookie));
+    settings.cookie.remove(session, "state");
+
+    // Reloading this page after the login finished
+    if let FlowCheck::Unknown = check {
+        if req.error.is_none() && try!(sessions.current(settings, session)).is_some() {
+            return Ok(Redirect::to("/"));
         }
+    }
 
-        let referer = headers[0];
+    // The state should exist in the set of active flows and the user should
+    // have a cookie with the identical state.
+    let state = try!(check.finish(&req));
+
+    // POST request for a token, proving possession of the PKCE verifier
+    // generated with the state.
+    let code = match req.code {
+        Some(ref code) => code.clone(),
+        None => return Err(OAuthError::HTTPError(Status::BadRequest)),
+    };
+    let token_rsp = try!(client.exchange_code(settings, code, state.code_verifier.clone()));
+
+    // An openid login must come back with an id_token signed by the
+    // provider for us, carrying the nonce we sent.
+    let subject = match token_rsp.id_token {
+        Some(ref id_token) => {
+            let nonce = state.nonce.as_ref().map(|nonce| nonce.as_str());
+            Some(try!(id_tokens.verify(client, id_token, nonce)).sub)
+        },
+        None if state.scopes.iter().any(|scope| scope == "openid") => {
+            return Err(OAuthError::JWTError("Token response has no id_token".to_string()));
+        },
+        None => None,
+    };
+
+    let scopes = token_rsp.scopes.clone();
+    let token_type = token_rsp.token_type.clone();
+    let token = token_rsp.access_token.clone();
+
+    // GET request for authorization
+    let data = try!(client.fetch_profile(settings, &token));
 
-        return Outcome::Success(Referer(referer.to_string()))
+    // The profile must belong to the user the id_token was issued for
+    if let Some(ref subject) = subject {
+        if *subject != data.uid {
+            return Err(OAuthError::JWTError(format!("id_token sub {} does not match profile uid {}", subject, data.uid)));
+        }
     }
+
+    // The tokens stay on the server, the browser only gets the signed
+    // session id. The profile is kept for /api/me.
+    let session_data = SessionData::new(&sessions.ttl(), data.uid.clone(), data.email.clone(), token, token_type, scopes.clone())
+        .set_refresh_token(token_rsp.refresh_token.clone())
+        .set_expires_in(token_rsp.expires_in)
+        .set_profile(Some(data.clone()));
+    let _ = try!(sessions.start(settings, session, session_data));
+
+    // Signed assertion downstream services can verify against
+    // /.well-known/jwks.json
+    let claims = SessionClaims::new(settings, data.uid.clone(), data.email.clone(), &scopes);
+    let assertion = try!(jwt::encode(&claims, keystore.signing_key()));
+    settings.cookie.add(session, "assertion", assertion);
+
+    // Return to wherever the login was started from
+    Ok(Redirect::to(&state.redirect_path()))
 }
 
-#[get("/oauth?<req>")]
-pub fn oauth(req: OAuthRequest, referer: Referer, settings: State<Settings>, flow: State<Flow>, session: &CookieJar) -> Result<Redirect, OAuthError> {
-    let referer = referer.0.clone();
-    let settings = settings.inner();
-    let flow = flow.inner();
+//--------------------------------------------------------------------------
+// Logout
+//--------------------------------------------------------------------------
 
-    // Check for user finishing flow in a different browser, prompt for login.
-    match req.error {
-        Some(err) => {
-            // Check for DifferentBrowserError
-            println!("OAuthError = {}", err);
-            Ok(Redirect::to("/?oauth_incomplete=true"))
-        },
+#[derive(Serialize)]
+pub struct CsrfResponse {
+    pub csrf_token: String,
+}
 
-        None => {
-
-            let state_cookie = session.find("state").unwrap();
-
-            // The state (nonce) should exist in set of active flows and
-            // the user should have a cookie with identical state.
-            match flow.verify(req.clone(), state_cookie.value().to_owned()) {
-                true => {
-                    // Delete the state (nonce) from the session
-                    let state = req.state.clone();
-
-                    flow.remove(state.clone());
-                    session.remove(state.clone());
-
-                    // POST request for a token
-                    let token_req = TokenRequest::new(settings, session);
-
-                    let json = try!(serde_json::to_string(&token_req));
-
-                    let client = try!(reqwest::Client::new());
-
-                    // POST TokenRequest
-                    let url = [settings.oauth_uri.clone(), "token".to_string()].join("/");
-                    let mut rsp = try!(client.post(url.as_str()).body(json.as_str()).send());
-
-                    let token_rsp: TokenResponse = try!(rsp.json());
-                    let scopes = token_rsp.scopes.clone();
-                    let token_type = token_rsp.token_type.clone();
-                    let token = token_rsp.access_token.clone();
-
-                    session.add(build_cookie("scopes".to_string(), scopes.join(" ")));
-                    session.add(build_cookie("token_type".to_string(), token_type.clone()));
-                    session.add(build_cookie("token".to_string(), token.clone()));
-
-                    // GET request for authorization
-                    let mut headers = Headers::new();
-                    let bearer = Bearer {
-                        token: token.to_owned()
-                    };
-                    headers.set(Authorization(bearer));
-
-                    let url = [settings.profile_uri.clone(), "profile".to_string()].join("/");
-                    let mut rsp = try!(client.get(url.as_str()).headers(headers).send());
-
-                    // if status >= 400, return status + rsp
-                    match rsp.status() {
-                        &StatusCode::BadRequest => {
-                            let json = try!(rsp.json());
-                            return Err(OAuthError::UnknownError(json));
-                        },
-
-                        &StatusCode::Ok => {
-                            let data: AccessResponse = try!(rsp.json());
-                            session.add(build_cookie("email".to_string(), data.email.clone()));
-                            session.add(build_cookie("uid".to_string(), data.uid.clone()));
-
-                            if referer.contains("/iframe") {
-                                Ok(Redirect::to("/iframe"))
-                            } else {
-                                Ok(Redirect::to("/"))
-                            }
-                        },
-
-                        _ => {
-                            let json = try!(rsp.json());
-                            return Err(OAuthError::UnknownError(json));
-                        },
-                    }
-                },
-
-                false => {
-                    match session.find("email") {
-                        Some(email) => {
-                            println!("session found email = {}", email.clone());
-                            // User is logged in
-                            Ok(Redirect::to("/"))
-                        },
-
-                        None => {
-                            // 400 - error bad request
-                            Err(OAuthError::HTTPError(Status::BadRequest))
-                        }
-                    }
-                }
+// The token the page must send back with POST /api/logout. Other origins
+// cannot read this response.
+#[get("/csrf")]
+pub fn csrf(user: AuthenticatedUser) -> JSON<CsrfResponse> {
+    JSON(CsrfResponse {
+        csrf_token: user.session.data.csrf_token.clone(),
+    })
+}
+
+#[derive(FromForm)]
+pub struct LogoutForm {
+    csrf_token: Option<String>,
+    redirect: Option<String>,
+}
+
+// Ends the session and revokes its access token. Takes the CSRF token as a
+// form field or an X-CSRF-Token header, so a plain form or fetch() can log
+// out but another site cannot.
+#[post("/logout", data = "<form>")]
+pub fn logout(form: Option<Form<LogoutForm>>, csrf: CsrfCheck, format: ErrorFormat, settings: State<Reloadable<Settings>>, client: State<Reloadable<ProviderClient>>, sessions: State<Sessions>, session: &CookieJar) -> Result<Redirect, ErrorResponse> {
+    let settings = settings.current();
+    let client = client.current();
+    let form = form.map(|form| form.into_inner())
+        .unwrap_or(LogoutForm { csrf_token: None, redirect: None });
+    end_session(form, csrf, &settings, &client, sessions.inner(), session)
+        .map_err(|err| err.respond_as(format))
+}
+
+fn end_session(form: LogoutForm, csrf: CsrfCheck, settings: &Settings, client: &ProviderClient, sessions: &Sessions, session: &CookieJar) -> Result<Redirect, OAuthError> {
+    let target = form.redirect.clone().unwrap_or("/".to_string());
+    if form.redirect.is_some() && !settings.allows_logout_redirect(&target) {
+        return Err(OAuthError::HTTPError(Status::BadRequest));
+    }
+
+    if let Some(current) = try!(sessions.current(settings, session)) {
+        let form_token = form.csrf_token.as_ref().map(|token| token.as_str());
+        if !csrf.verify(settings, &current, form_token) {
+            return Err(OAuthError::HTTPError(Status::Forbidden));
+        }
+
+        try!(sessions.end(settings, session, &current));
+
+        // The user is logged out either way, a token the provider did not
+        // revoke just runs until it expires.
+        if let Err(err) = client.revoke(settings, current.data.access_token.clone(), "access_token") {
+            println!("Token revocation failed: {}", err);
+        }
+        if let Some(ref refresh_token) = current.data.refresh_token {
+            if let Err(err) = client.revoke(settings, refresh_token.clone(), "refresh_token") {
+                println!("Refresh token revocation failed: {}", err);
             }
         }
     }
+
+    // Also when the session already expired, its assertion must not outlive it
+    settings.cookie.remove(session, "assertion");
+
+    Ok(Redirect::to(&target))
 }
 
 //--------------------------------------------------------------------------
 // Known Public Keys
 //--------------------------------------------------------------------------
 
+impl<'a> From<&'a PublicKey> for PublicKeyResponse {
+    fn from(public_key: &'a PublicKey) -> PublicKeyResponse {
+        let mut response = PublicKeyResponse {
+            kid: public_key.kid().to_string(),
+            _use: "sig".to_string(),
+            alg: public_key.algorithm().name().to_string(),
+            kty: public_key.kty().to_string(),
+            n: None,
+            e: None,
+            crv: None,
+            x: None,
+            y: None,
+        };
+
+        match *public_key {
+            PublicKey::RSA { ref n, ref e, .. } => {
+                response.n = Some(n.clone());
+                response.e = Some(e.clone());
+            },
+            PublicKey::EC { ref crv, ref x, ref y, .. } => {
+                response.crv = Some(crv.clone());
+                response.x = Some(x.clone());
+                response.y = Some(y.clone());
+            },
+            PublicKey::OKP { ref crv, ref x, .. } => {
+                response.crv = Some(crv.clone());
+                response.x = Some(x.clone());
+            },
+        }
+
+        response
+    }
+}
+
+// The key currently signing assertions
 #[get("/.well-known/public-keys")]
-fn well_known(public: State<PublicKey>) -> JSON<PublicKeyResponse> {
-    let public_key = public.inner();
-    JSON(PublicKeyResponse {
-        kid: public_key.kid.clone(),
-        _use: "sig".to_string(),
-        kty: public_key.kty.clone(),
-        n: public_key.n.clone(),
-        e: public_key.e.clone(),
+fn well_known(keystore: State<Reloadable<KeyStore>>) -> Option<JSON<PublicKeyResponse>> {
+    keystore.current().current().map(|public_key| JSON(PublicKeyResponse::from(public_key)))
+}
+
+// Every published key: next, current and retiring
+#[get("/.well-known/jwks.json")]
+fn jwks(keystore: State<Reloadable<KeyStore>>) -> JSON<JwkSetResponse> {
+    let keystore = keystore.current();
+    JSON(JwkSetResponse {
+        keys: keystore.published().iter().map(PublicKeyResponse::from).collect(),
     })
 }
@@ -0,0 +1,65 @@
+use rocket::Outcome;
+use rocket::State;
+use rocket::http::CookieJar;
+use rocket::request::{self, Request, FromRequest, FormItems};
+use rocket_contrib::JSON;
+use oauth::settings::Settings;
+use oauth::reload::Reloadable;
+use oauth::session::Sessions;
+use oauth::guard::AuthenticatedUser;
+use oauth::AccessResponse;
+use oauth::client::ProviderClient;
+use error::OAuthError;
+
+//--------------------------------------------------------------------------
+// Profile Parameters
+//--------------------------------------------------------------------------
+
+// Reads the optional `refresh` query parameter, ?refresh=true or ?refresh=1
+#[derive(Default)]
+pub struct ProfileParams {
+    pub refresh: bool,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ProfileParams {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ProfileParams, ()> {
+        let mut params = ProfileParams::default();
+
+        if let Some(query) = request.uri().query() {
+            for (key, value) in FormItems::from(query) {
+                if key == "refresh" {
+                    params.refresh = value == "true" || value == "1";
+                }
+            }
+        }
+
+        Outcome::Success(params)
+    }
+}
+
+//--------------------------------------------------------------------------
+// Routes
+//--------------------------------------------------------------------------
+
+// The profile of the logged in user, as fetched at login. With refresh it
+// is fetched again with the session's access token and kept for next time.
+#[get("/me")]
+pub fn me(user: AuthenticatedUser, params: ProfileParams, settings: State<Reloadable<Settings>>, client: State<Reloadable<ProviderClient>>, sessions: State<Sessions>, session: &CookieJar) -> Result<JSON<AccessResponse>, OAuthError> {
+    let settings = settings.current();
+    let client = client.current();
+    let mut current = user.session;
+
+    match current.data.profile {
+        Some(ref profile) if !params.refresh => return Ok(JSON(profile.clone())),
+        _ => {},
+    }
+
+    // Sessions from before profiles were kept have none yet
+    let profile = try!(client.fetch_profile(&settings, &current.data.access_token));
+    current.data.profile = Some(profile.clone());
+    try!(sessions.save(&settings, session, &current));
+
+    Ok(JSON(profile))
+}
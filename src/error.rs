@@ -1,9 +1,17 @@
-
 use rocket;
+use rocket::Outcome;
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, Request, FromRequest};
+use rocket::response::{self, Responder, Response};
 use reqwest;
 use serde_json;
 use openssl;
-use std::io::{self};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Cursor, Read};
+
+// Where a page starts a login, see controllers::oauth::login
+pub const LOGIN_PATH: &'static str = "/api/login";
 
 #[derive(Debug)]
 pub enum OAuthError {
@@ -12,10 +20,270 @@ pub enum OAuthError {
     HTTPError(rocket::http::Status),
     HTTPClientError(reqwest::Error),
     OpenSSLError(openssl::error::ErrorStack),
+    JWTError(String),
     DifferentBrowserError(String),
+    // A callback for a state that is unknown, used or expired
+    LoginExpired,
+    // Nobody is logged in
+    LoginRequired,
+    // The session was not granted this scope
+    MissingScope(String),
+    // An RFC 6749 error the provider answered with, and its description
+    ProviderError(ProviderError, Option<String>),
     UnknownError(String),
 }
 
+//--------------------------------------------------------------------------
+// Provider Errors
+//--------------------------------------------------------------------------
+
+// Error codes of RFC 6749 sections 4.1.2.1 and 5.2
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProviderError {
+    AccessDenied,
+    InvalidRequest,
+    InvalidClient,
+    InvalidGrant,
+    InvalidScope,
+    UnauthorizedClient,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    ServerError,
+    TemporarilyUnavailable,
+    Other(String),
+}
+
+impl ProviderError {
+
+    pub fn from_code(code: &str) -> ProviderError {
+        match code {
+            "access_denied" => ProviderError::AccessDenied,
+            "invalid_request" => ProviderError::InvalidRequest,
+            "invalid_client" => ProviderError::InvalidClient,
+            "invalid_grant" => ProviderError::InvalidGrant,
+            "invalid_scope" => ProviderError::InvalidScope,
+            "unauthorized_client" => ProviderError::UnauthorizedClient,
+            "unsupported_grant_type" => ProviderError::UnsupportedGrantType,
+            "unsupported_response_type" => ProviderError::UnsupportedResponseType,
+            "server_error" => ProviderError::ServerError,
+            "temporarily_unavailable" => ProviderError::TemporarilyUnavailable,
+            other => ProviderError::Other(other.to_string()),
+        }
+    }
+
+    // The code clients see. Codes outside RFC 6749 could say anything, so
+    // they are only logged.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ProviderError::AccessDenied => "access_denied",
+            ProviderError::InvalidRequest => "invalid_request",
+            ProviderError::InvalidClient => "invalid_client",
+            ProviderError::InvalidGrant => "invalid_grant",
+            ProviderError::InvalidScope => "invalid_scope",
+            ProviderError::UnauthorizedClient => "unauthorized_client",
+            ProviderError::UnsupportedGrantType => "unsupported_grant_type",
+            ProviderError::UnsupportedResponseType => "unsupported_response_type",
+            ProviderError::ServerError => "server_error",
+            ProviderError::TemporarilyUnavailable => "temporarily_unavailable",
+            ProviderError::Other(_) => "provider_error",
+        }
+    }
+
+    // The code as the provider sent it
+    pub fn raw_code(&self) -> &str {
+        match *self {
+            ProviderError::Other(ref code) => code.as_str(),
+            _ => self.code(),
+        }
+    }
+}
+
+// An error body as sent by RFC 6749 providers, or by FxA which puts the
+// description in message.
+#[derive(Deserialize)]
+struct ProviderErrorBody {
+    error: Option<String>,
+    error_description: Option<String>,
+    message: Option<String>,
+}
+
+impl OAuthError {
+
+    // Reads the error out of a failed back channel response
+    pub fn from_response(rsp: &mut reqwest::Response) -> OAuthError {
+        let mut body = String::new();
+        if let Err(err) = rsp.read_to_string(&mut body) {
+            return OAuthError::IOError(err);
+        }
+        let status = rsp.status().to_string();
+        OAuthError::from_provider_response(&status, &body)
+    }
+
+    // The error a provider answered a back channel request with
+    pub fn from_provider_response(status: &str, body: &str) -> OAuthError {
+        match serde_json::from_str::<ProviderErrorBody>(body) {
+            Ok(ProviderErrorBody { error: Some(error), error_description, message }) => {
+                OAuthError::ProviderError(ProviderError::from_code(&error), error_description.or(message))
+            },
+            _ => {
+                let excerpt: String = body.chars().take(200).collect();
+                OAuthError::UnknownError(format!("Provider responded {}: {}", status, excerpt))
+            },
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Codes
+//--------------------------------------------------------------------------
+
+impl OAuthError {
+
+    // Stable, machine readable code clients may rely on
+    pub fn code(&self) -> &str {
+        match *self {
+            OAuthError::IOError(_) |
+            OAuthError::JSONError(_) |
+            OAuthError::OpenSSLError(_) |
+            OAuthError::UnknownError(_) => "internal_error",
+            OAuthError::HTTPError(status) => match status.code {
+                400 => "bad_request",
+                401 => "unauthorized",
+                403 => "forbidden",
+                404 => "not_found",
+                _ => "http_error",
+            },
+            OAuthError::HTTPClientError(_) => "provider_unreachable",
+            OAuthError::JWTError(_) => "invalid_token",
+            OAuthError::DifferentBrowserError(_) => "different_browser",
+            OAuthError::LoginExpired => "login_expired",
+            OAuthError::LoginRequired => "login_required",
+            OAuthError::MissingScope(_) => "insufficient_scope",
+            OAuthError::ProviderError(ref error, _) => error.code(),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match *self {
+            OAuthError::HTTPError(status) => status,
+            OAuthError::HTTPClientError(_) => Status::BadGateway,
+            OAuthError::JWTError(_) => Status::Unauthorized,
+            OAuthError::DifferentBrowserError(_) => Status::BadRequest,
+            OAuthError::LoginExpired => Status::BadRequest,
+            OAuthError::LoginRequired => Status::Unauthorized,
+            OAuthError::MissingScope(_) => Status::Forbidden,
+            OAuthError::ProviderError(ref error, _) => match *error {
+                ProviderError::AccessDenied => Status::Forbidden,
+                ProviderError::InvalidGrant |
+                ProviderError::InvalidRequest |
+                ProviderError::InvalidScope => Status::BadRequest,
+                ProviderError::TemporarilyUnavailable => Status::ServiceUnavailable,
+                _ => Status::BadGateway,
+            },
+            _ => Status::InternalServerError,
+        }
+    }
+
+    // Where a client sent away with 401 can start a login
+    pub fn login_hint(&self) -> Option<&'static str> {
+        match *self {
+            OAuthError::LoginRequired => Some(LOGIN_PATH),
+            _ => None,
+        }
+    }
+
+    pub fn missing_scope(&self) -> Option<&str> {
+        match *self {
+            OAuthError::MissingScope(ref scope) => Some(scope.as_str()),
+            _ => None,
+        }
+    }
+
+    // What the client is told. Details stay in the log.
+    pub fn public_message(&self) -> &'static str {
+        match *self {
+            OAuthError::HTTPError(status) => match status.code {
+                400 => "The request was invalid.",
+                401 => "You need to log in.",
+                403 => "You are not allowed to do that.",
+                404 => "Not found.",
+                _ => "The request failed.",
+            },
+            OAuthError::HTTPClientError(_) => "The login provider could not be reached. Please try again.",
+            OAuthError::JWTError(_) => "The login could not be verified. Please log in again.",
+            OAuthError::DifferentBrowserError(_) => "The login was finished in a different browser. Please log in again here.",
+            OAuthError::LoginExpired => "The login took too long or was already finished. Please log in again.",
+            OAuthError::LoginRequired => "You need to log in.",
+            OAuthError::MissingScope(_) => "You have not granted the access this needs. Please log in again and allow it.",
+            OAuthError::ProviderError(ref error, _) => match *error {
+                ProviderError::AccessDenied => "The login was cancelled.",
+                ProviderError::InvalidGrant => "The login expired or was already used. Please log in again.",
+                ProviderError::TemporarilyUnavailable => "The login provider is temporarily unavailable. Please try again.",
+                ProviderError::ServerError => "The login provider ran into a problem. Please try again.",
+                _ => "The login provider refused the request.",
+            },
+            _ => "Something went wrong on our side. Please try again.",
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Display / Error
+//--------------------------------------------------------------------------
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OAuthError::IOError(ref err) => write!(f, "I/O error: {}", err),
+            OAuthError::JSONError(ref err) => write!(f, "JSON error: {}", err),
+            OAuthError::HTTPError(status) => write!(f, "HTTP {} {}", status.code, status.reason),
+            OAuthError::HTTPClientError(ref err) => write!(f, "HTTP client error: {}", err),
+            OAuthError::OpenSSLError(ref err) => write!(f, "OpenSSL error: {}", err),
+            OAuthError::JWTError(ref message) => write!(f, "Token rejected: {}", message),
+            OAuthError::DifferentBrowserError(ref message) => write!(f, "Login finished in a different browser: {}", message),
+            OAuthError::LoginExpired => write!(f, "Login state unknown or expired"),
+            OAuthError::LoginRequired => write!(f, "Login required"),
+            OAuthError::MissingScope(ref scope) => write!(f, "Missing scope {}", scope),
+            OAuthError::ProviderError(ref error, Some(ref description)) => write!(f, "Provider error {}: {}", error.raw_code(), description),
+            OAuthError::ProviderError(ref error, None) => write!(f, "Provider error {}", error.raw_code()),
+            OAuthError::UnknownError(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for OAuthError {
+    fn description(&self) -> &str {
+        match *self {
+            OAuthError::IOError(_) => "I/O error",
+            OAuthError::JSONError(_) => "JSON error",
+            OAuthError::HTTPError(_) => "HTTP error",
+            OAuthError::HTTPClientError(_) => "HTTP client error",
+            OAuthError::OpenSSLError(_) => "OpenSSL error",
+            OAuthError::JWTError(_) => "token rejected",
+            OAuthError::DifferentBrowserError(_) => "login finished in a different browser",
+            OAuthError::LoginExpired => "login expired",
+            OAuthError::LoginRequired => "login required",
+            OAuthError::MissingScope(_) => "missing scope",
+            OAuthError::ProviderError(_, _) => "provider error",
+            OAuthError::UnknownError(_) => "unknown error",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            OAuthError::IOError(ref err) => Some(err),
+            OAuthError::JSONError(ref err) => Some(err),
+            OAuthError::HTTPClientError(ref err) => Some(err),
+            OAuthError::OpenSSLError(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Conversions
+//--------------------------------------------------------------------------
+
 impl From<io::Error> for OAuthError {
     fn from(err: io::Error) -> OAuthError {
         OAuthError::IOError(err)
@@ -46,8 +314,139 @@ impl From<reqwest::Error> for OAuthError {
     }
 }
 
-impl From<String> for OAuthError {
-    fn from(err: String) -> OAuthError {
-        OAuthError::DifferentBrowserError(err)
+//--------------------------------------------------------------------------
+// Responses
+//--------------------------------------------------------------------------
+
+// How a handler's client wants errors, from its Accept header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorFormat {
+    JSON,
+    HTML,
+}
+
+// The q value Accept gives a media type, 0 when it is not listed
+fn quality(accept: &str, media_type: &str) -> f32 {
+    accept.split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';').map(|param| param.trim());
+            match params.next() {
+                Some(name) if name.to_lowercase() == media_type => {
+                    let q = params
+                        .filter(|param| param.starts_with("q="))
+                        .filter_map(|param| param[2..].parse().ok())
+                        .next();
+                    Some(q.unwrap_or(1.0))
+                },
+                _ => None,
+            }
+        })
+        .next()
+        .unwrap_or(0.0)
+}
+
+impl ErrorFormat {
+
+    // Browsers navigating to a page ask for text/html, API clients for JSON
+    // or anything. Ties go to JSON.
+    pub fn from_accept(accept: Option<&str>) -> ErrorFormat {
+        match accept {
+            Some(accept) if quality(accept, "text/html") > quality(accept, "application/json") => ErrorFormat::HTML,
+            _ => ErrorFormat::JSON,
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ErrorFormat {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ErrorFormat, ()> {
+        Outcome::Success(ErrorFormat::from_accept(request.headers().get_one("Accept")))
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    error_description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    login: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// An error rendered for the client in the format it asked for
+#[derive(Debug)]
+pub struct ErrorResponse {
+    pub error: OAuthError,
+    pub format: ErrorFormat,
+}
+
+impl ErrorResponse {
+
+    pub fn body(&self) -> String {
+        let code = self.error.code();
+        let message = self.error.public_message();
+        match self.format {
+            ErrorFormat::JSON => {
+                let body = ErrorBody {
+                    error: code.to_string(),
+                    error_description: message.to_string(),
+                    login: self.error.login_hint().map(|login| login.to_string()),
+                    scope: self.error.missing_scope().map(|scope| scope.to_string()),
+                };
+                serde_json::to_string(&body).unwrap_or(String::new())
+            },
+            ErrorFormat::HTML => {
+                let detail = match self.error.missing_scope() {
+                    Some(scope) => format!("<p>Missing scope <code>{}</code></p>\n", escape_html(scope)),
+                    None => String::new(),
+                };
+                format!(
+                    "<!DOCTYPE html>\n<html lang=\"en\">\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>{}</h1>\n<p>{}</p>\n{}<p><code>{}</code></p>\n<p><a href=\"/\">Back to the start page</a></p>\n</body>\n</html>\n",
+                    escape_html(self.error.status().reason), escape_html(self.error.status().reason),
+                    escape_html(message), detail, escape_html(code))
+            },
+        }
+    }
+}
+
+impl OAuthError {
+    pub fn respond_as(self, format: ErrorFormat) -> ErrorResponse {
+        ErrorResponse {
+            error: self,
+            format: format,
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ErrorResponse {
+    fn respond(self) -> response::Result<'r> {
+        println!("OAuthError = {}", self.error);
+
+        let content_type = match self.format {
+            ErrorFormat::JSON => ContentType::JSON,
+            ErrorFormat::HTML => ContentType::HTML,
+        };
+        Response::build()
+            .status(self.error.status())
+            .header(content_type)
+            .sized_body(Cursor::new(self.body()))
+            .ok()
+    }
+}
+
+// Handlers which do not ask for an ErrorFormat answer with JSON
+impl<'r> Responder<'r> for OAuthError {
+    fn respond(self) -> response::Result<'r> {
+        self.respond_as(ErrorFormat::JSON).respond()
     }
 }
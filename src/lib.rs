@@ -11,6 +11,8 @@ extern crate rustc_serialize;
 extern crate serde;
 extern crate serde_json;
 extern crate rand;
+extern crate libc;
+extern crate time;
 extern crate dotenv;
 
 pub mod oauth;
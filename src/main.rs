@@ -13,8 +13,10 @@ extern crate handlebars;
 extern crate reqwest;
 extern crate dotenv;
 extern crate rand;
+extern crate libc;
+extern crate time;
 
-use rocket::http::{CookieJar};
+use rocket::http::Status;
 use rocket::request::Request;
 use handlebars::Handlebars;
 use std::path::{Path};
@@ -23,9 +25,13 @@ pub mod oauth;
 pub mod controllers;
 pub mod error;
 
-use oauth::settings::{self};
+use oauth::settings::{self, DotEnv};
 use oauth::flow::Flow;
-use oauth::crypto::{self};
+use oauth::crypto::keystore::KeyStore;
+use oauth::reload::Reloader;
+use oauth::session::Sessions;
+use oauth::client::ProviderClient;
+use error::{OAuthError, ErrorFormat, ErrorResponse};
 
 //--------------------------------------------------------------------------
 // Templates
@@ -41,27 +47,69 @@ fn register_templates(handlebars: &mut Handlebars) {
 // Errors
 //--------------------------------------------------------------------------
 
+#[error(401)]
+fn unauthorized(request: &Request) -> ErrorResponse {
+    let format = ErrorFormat::from_accept(request.headers().get_one("Accept"));
+    OAuthError::LoginRequired.respond_as(format)
+}
+
+#[error(403)]
+fn forbidden(request: &Request) -> ErrorResponse {
+    let format = ErrorFormat::from_accept(request.headers().get_one("Accept"));
+    OAuthError::HTTPError(Status::Forbidden).respond_as(format)
+}
+
 #[error(404)]
-fn not_found(_: &Request) -> String {
-    String::from("404 - Not found")
+fn not_found(request: &Request) -> ErrorResponse {
+    let format = ErrorFormat::from_accept(request.headers().get_one("Accept"));
+    OAuthError::HTTPError(Status::NotFound).respond_as(format)
 }
 
 //--------------------------------------------------------------------------
 // Start
 //--------------------------------------------------------------------------
 
+// Prints why the server cannot start, like settings problems
+fn or_exit<T>(result: Result<T, OAuthError>, what: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            println!("Unable to load {}: {}", what, err);
+            std::process::exit(1);
+        },
+    }
+}
+
 fn main() {
-    let settings = settings::read_settings();
-    let public_key = crypto::read_public_key(settings.clone()).unwrap();
-    let _ = CookieJar::new(settings.client_secret.as_bytes());
+    let dotenv = DotEnv::new();
+    let settings = match settings::read_settings(&dotenv) {
+        Ok(settings) => settings,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        },
+    };
+    println!("Using profile {} against {}", settings.profile.name(), settings.endpoints.issuer);
+    let keystore = or_exit(KeyStore::from_settings(&settings), "keys");
     let mut handlebars = Handlebars::new();
     register_templates(&mut handlebars);
-    let flow = Flow::new();
+    // Pending logins and sessions outlive reloads, everything else is
+    // swapped on SIGHUP or when the config / key files change.
+    let flow = Flow::from_settings(&settings);
+    let sessions = or_exit(Sessions::from_settings(&settings), "sessions");
+    let client = or_exit(ProviderClient::from_settings(&settings), "provider client");
+    let reloader = Reloader::new(dotenv, settings, keystore, client);
+    let settings = reloader.settings.clone();
+    let keystore = reloader.keystore.clone();
+    let id_tokens = reloader.id_tokens.clone();
+    let client = reloader.client.clone();
+    reloader.watch();
 
     rocket::ignite()
         // Routes
         .mount("/", routes![
             controllers::oauth::well_known,
+            controllers::oauth::jwks,
         ])
 
         .mount("/api", routes![
@@ -70,16 +118,23 @@ fn main() {
             controllers::oauth::best_choice,
             controllers::oauth::force_auth,
             controllers::oauth::oauth,
+            controllers::oauth::csrf,
+            controllers::oauth::logout,
+            controllers::profile::me,
+            controllers::diagnostics::diagnostics,
         ])
 
         // Shared state
         .manage(settings)
-        .manage(public_key)
+        .manage(keystore)
         .manage(flow)
+        .manage(sessions)
+        .manage(client)
+        .manage(id_tokens)
         .manage(HandlebarsState(handlebars))
 
         // Errors
-        .catch(errors![not_found])
+        .catch(errors![unauthorized, forbidden, not_found])
 
         // Go
         .launch();
@@ -0,0 +1,216 @@
+use std::cmp;
+use std::error::Error;
+use std::io;
+use std::thread;
+use std::time::Duration;
+use reqwest::{self, Client, RequestBuilder, Response};
+use reqwest::header::{Headers, Bearer, Authorization};
+use serde::Serialize;
+use super::settings::Settings;
+use super::{TokenRequest, TokenResponse, RefreshRequest, RevocationRequest, AccessResponse};
+use error::OAuthError;
+
+// Seconds a call to the provider may take
+pub const DEFAULT_TIMEOUT: u64 = 10;
+
+// Times a failed call is repeated, see Retry for which failures count
+pub const DEFAULT_RETRIES: u64 = 2;
+
+// Wait before the first retry, doubled for each one after
+pub const DEFAULT_BACKOFF_MS: u64 = 200;
+
+//--------------------------------------------------------------------------
+// Encoding
+//--------------------------------------------------------------------------
+
+// How a provider takes request bodies. Providers found by discovery are
+// assumed to follow RFC 6749 and RFC 7009 and take forms, the FxA layout
+// takes JSON.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Form,
+    Json,
+}
+
+impl Encoding {
+
+    pub fn for_provider(settings: &Settings) -> Encoding {
+        match settings.discovery_issuer {
+            Some(_) => Encoding::Form,
+            None => Encoding::Json,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Retry
+//--------------------------------------------------------------------------
+
+// Which failures a call is repeated after
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Retry {
+    // Network errors and 5xx answers, for calls that are safe to repeat
+    Always,
+    // Only connections the provider refused. Codes are single use and
+    // refresh tokens may be rotated, so a token request the provider might
+    // have seen is never sent twice.
+    Unsent,
+}
+
+impl Retry {
+
+    fn after_answer(&self, status: &reqwest::StatusCode) -> bool {
+        *self == Retry::Always && status.is_server_error()
+    }
+
+    fn after_error(&self, err: &reqwest::Error) -> bool {
+        *self == Retry::Always || never_sent(err)
+    }
+}
+
+// Whether the connection was refused, so nothing reached the provider.
+// Timeouts and resets can happen after it got the request.
+pub fn never_sent(err: &(Error + 'static)) -> bool {
+    let mut cause = Some(err);
+    while let Some(err) = cause {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            return err.kind() == io::ErrorKind::ConnectionRefused;
+        }
+        cause = err.source();
+    }
+    false
+}
+
+//--------------------------------------------------------------------------
+// ProviderClient
+//--------------------------------------------------------------------------
+
+// Talks to the provider's token, profile and revocation endpoints over one
+// reused connection pool. Endpoints come from the settings passed to each
+// call, so they follow reloads.
+pub struct ProviderClient {
+    http: Client,
+    retries: u64,
+    backoff: Duration,
+}
+
+impl ProviderClient {
+
+    pub fn new(timeout: Duration, retries: u64, backoff: Duration) -> Result<ProviderClient, OAuthError> {
+        let mut http = try!(Client::new());
+        http.timeout(timeout);
+
+        Ok(ProviderClient {
+            http: http,
+            retries: retries,
+            backoff: backoff,
+        })
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<ProviderClient, OAuthError> {
+        ProviderClient::new(Duration::from_secs(settings.provider_timeout),
+                            settings.provider_retries,
+                            Duration::from_millis(DEFAULT_BACKOFF_MS))
+    }
+
+    // How long to wait before retry number attempt, counting from 1
+    pub fn backoff(&self, attempt: u64) -> Duration {
+        let doublings = cmp::min(attempt.saturating_sub(1), 16) as u32;
+        self.backoff * (1 << doublings)
+    }
+
+    // Sends the request build makes, building it again for each retry.
+    // Answers retry does not cover are returned as they are.
+    fn send<F>(&self, retry: Retry, build: F) -> Result<Response, OAuthError>
+        where F: Fn(&Client) -> RequestBuilder
+    {
+        let mut attempt = 0;
+        loop {
+            match build(&self.http).send() {
+                Ok(rsp) => {
+                    if !retry.after_answer(rsp.status()) || attempt >= self.retries {
+                        return Ok(rsp);
+                    }
+                    println!("Provider answered {}, retrying", rsp.status());
+                },
+                Err(err) => {
+                    if !retry.after_error(&err) || attempt >= self.retries {
+                        return Err(OAuthError::from(err));
+                    }
+                    println!("Provider unreachable ({}), retrying", err);
+                },
+            }
+
+            attempt += 1;
+            thread::sleep(self.backoff(attempt));
+        }
+    }
+
+    // Like send, turning answers other than 2xx into errors
+    fn send_checked<F>(&self, retry: Retry, build: F) -> Result<Response, OAuthError>
+        where F: Fn(&Client) -> RequestBuilder
+    {
+        let mut rsp = try!(self.send(retry, build));
+        if !rsp.status().is_success() {
+            return Err(OAuthError::from_response(&mut rsp));
+        }
+        Ok(rsp)
+    }
+
+    // POSTs body encoded the way the provider takes it
+    fn post<T: Serialize>(&self, settings: &Settings, retry: Retry, url: &str, body: &T) -> Result<Response, OAuthError> {
+        match Encoding::for_provider(settings) {
+            Encoding::Form => self.send_checked(retry, |http| http.post(url).form(body)),
+            Encoding::Json => self.send_checked(retry, |http| http.post(url).json(body)),
+        }
+    }
+
+    // GETs a provider document such as its JWK Set, retrying like the other
+    // safe calls. The answer is returned as it is.
+    pub fn get(&self, url: &str) -> Result<Response, OAuthError> {
+        self.send(Retry::Always, |http| http.get(url))
+    }
+
+    // Trades an authorization code for tokens
+    pub fn exchange_code(&self, settings: &Settings, code: String, code_verifier: Option<String>) -> Result<TokenResponse, OAuthError> {
+        let request = TokenRequest::new(settings, code, code_verifier);
+
+        let mut rsp = try!(self.post(settings, Retry::Unsent, &settings.endpoints.token, &request));
+        Ok(try!(rsp.json()))
+    }
+
+    // GET the profile of the user an access token was issued for
+    pub fn fetch_profile(&self, settings: &Settings, access_token: &str) -> Result<AccessResponse, OAuthError> {
+        let mut headers = Headers::new();
+        headers.set(Authorization(Bearer {
+            token: access_token.to_owned()
+        }));
+        let url = settings.endpoints.userinfo.as_str();
+
+        let mut rsp = try!(self.send_checked(Retry::Always, |http| http.get(url).headers(headers.clone())));
+        Ok(try!(rsp.json()))
+    }
+
+    // Trades a refresh token for a new access token. Also called by the
+    // Session guard, which only waits for a retry when the provider
+    // refused the connection.
+    pub fn refresh(&self, settings: &Settings, refresh_token: String) -> Result<TokenResponse, OAuthError> {
+        let request = RefreshRequest::new(settings, refresh_token);
+
+        let mut rsp = try!(self.post(settings, Retry::Unsent, &settings.endpoints.token, &request));
+        Ok(try!(rsp.json()))
+    }
+
+    // Revokes an access or refresh token at the provider, /destroy for the
+    // FxA layout. Does nothing when the provider has no revocation endpoint.
+    pub fn revoke(&self, settings: &Settings, token: String, token_type_hint: &str) -> Result<(), OAuthError> {
+        let url = match settings.endpoints.revocation {
+            Some(ref url) => url.as_str(),
+            None => return Ok(()),
+        };
+        let request = RevocationRequest::new(settings, token, token_type_hint);
+
+        let _ = try!(self.post(settings, Retry::Always, url, &request));
+        Ok(())
+    }
+}
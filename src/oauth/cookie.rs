@@ -0,0 +1,140 @@
+use rocket::http::{Cookie, CookieJar};
+use time::Duration;
+
+//--------------------------------------------------------------------------
+// SameSite
+//--------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SameSite> {
+        match name.to_lowercase().as_str() {
+            "strict" => Some(SameSite::Strict),
+            "lax" => Some(SameSite::Lax),
+            "none" => Some(SameSite::None),
+            _ => None,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// CookiePolicy
+//--------------------------------------------------------------------------
+
+// Attributes shared by every cookie we set. Cookies are always HttpOnly.
+#[derive(Clone, Debug)]
+pub struct CookiePolicy {
+    pub domain: Option<String>,
+    pub path: String,
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+    // Seconds, session cookies when None
+    pub max_age: Option<u64>,
+    // Prepended to every name, e.g. __Host-
+    pub prefix: String,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> CookiePolicy {
+        CookiePolicy {
+            domain: None,
+            path: "/api".to_string(),
+            secure: false,
+            same_site: Some(SameSite::Lax),
+            max_age: None,
+            prefix: String::new(),
+        }
+    }
+}
+
+impl CookiePolicy {
+
+    // The name a cookie is stored under once prefixed
+    pub fn name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    pub fn build(&self, name: &str, value: String) -> Cookie<'static> {
+        // cookie 0.6, which Rocket 0.2 uses, predates SameSite. Parameters
+        // are written verbatim after Path, so the attribute rides along.
+        let path = match self.same_site {
+            Some(same_site) => format!("{}; SameSite={}", self.path, same_site.name()),
+            None => self.path.clone(),
+        };
+
+        let mut cookie = Cookie::build(self.name(name), value)
+            .path(path)
+            .secure(self.secure)
+            .http_only(true);
+        if let Some(ref domain) = self.domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        if let Some(max_age) = self.max_age {
+            cookie = cookie.max_age(Duration::seconds(max_age as i64));
+        }
+        cookie.finish()
+    }
+
+    // An already expired cookie with the same Path and Domain, which is what
+    // a browser needs to forget it. CookieJar::remove sends neither.
+    pub fn expire(&self, name: &str) -> Cookie<'static> {
+        let mut cookie = self.build(name, String::new());
+        cookie.set_max_age(Duration::zero());
+        cookie
+    }
+
+    pub fn find(&self, jar: &CookieJar, name: &str) -> Option<String> {
+        jar.find(&self.name(name))
+            .map(|cookie| cookie.value().to_string())
+    }
+
+    pub fn add(&self, jar: &CookieJar, name: &str, value: String) {
+        jar.add(self.build(name, value));
+    }
+
+    pub fn remove(&self, jar: &CookieJar, name: &str) {
+        jar.add(self.expire(name));
+    }
+
+    // Browsers reject __Host- and __Secure- cookies which break the rules
+    // of their prefix, and SameSite=None without Secure.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.prefix.starts_with("__Secure-") || self.prefix.starts_with("__Host-") {
+            if !self.secure {
+                problems.push(format!("COOKIE_PREFIX {} requires COOKIE_SECURE=true", self.prefix));
+            }
+        }
+        if self.prefix.starts_with("__Host-") {
+            if self.domain.is_some() {
+                problems.push("COOKIE_PREFIX __Host- forbids COOKIE_DOMAIN".to_string());
+            }
+            if self.path != "/" {
+                problems.push("COOKIE_PREFIX __Host- requires COOKIE_PATH=/".to_string());
+            }
+        }
+        if self.same_site == Some(SameSite::None) && !self.secure {
+            problems.push("COOKIE_SAME_SITE=none requires COOKIE_SECURE=true".to_string());
+        }
+        if !self.path.starts_with('/') || self.path.contains(';') {
+            problems.push(format!("COOKIE_PATH {:?} must be a path", self.path));
+        }
+
+        problems
+    }
+}
@@ -0,0 +1,151 @@
+use openssl::hash::{hash, MessageDigest};
+use openssl::rand::rand_bytes;
+use openssl::symm::{Cipher, encrypt_aead, decrypt_aead};
+use rustc_serialize::base64::{self, ToBase64, FromBase64};
+use oauth::settings::Settings;
+use error::OAuthError;
+
+// Seals values kept in cookies with AES-256-GCM. A sealed value is
+//
+//   <kid>.<iv>.<ciphertext>.<tag>
+//
+// base64url encoded, where kid names the key it was sealed with. The cookie
+// name and kid are authenticated too, so a value cannot be moved to another
+// cookie. The first configured key seals, every configured key opens, which
+// lets keys be rotated without logging everybody out.
+
+pub const KEY_SIZE: usize = 32;
+const IV_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+// Browsers keep at least 4096 bytes per cookie including its name and
+// attributes, so chunks stay well below that.
+pub const CHUNK_SIZE: usize = 3800;
+pub const MAX_CHUNKS: usize = 4;
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.to_base64(base64::URL_SAFE)
+}
+
+// A base64url encoded 256-bit key, as found in SESSION_KEYS
+pub fn decode_key(value: &str) -> Result<Vec<u8>, OAuthError> {
+    match value.trim().from_base64() {
+        Ok(ref key) if key.len() == KEY_SIZE => Ok(key.clone()),
+        _ => Err(OAuthError::UnknownError("Session keys must be base64url encoded 256-bit keys".to_string())),
+    }
+}
+
+// A fresh base64url encoded session key
+pub fn generate_key() -> Result<String, OAuthError> {
+    let mut key = vec![0u8; KEY_SIZE];
+    try!(rand_bytes(&mut key));
+    Ok(encode(&key))
+}
+
+// Derived from the key so it can be published in values without revealing it
+pub fn key_id(key: &[u8]) -> Result<String, OAuthError> {
+    let digest = try!(hash(MessageDigest::sha256(), key));
+    Ok(encode(&digest[..6]))
+}
+
+struct SessionKey {
+    kid: String,
+    key: Vec<u8>,
+}
+
+pub struct SessionCodec {
+    keys: Vec<SessionKey>,
+}
+
+impl SessionCodec {
+
+    // The first key seals new values, the others only open existing ones
+    pub fn new(keys: Vec<Vec<u8>>) -> Result<SessionCodec, OAuthError> {
+        if keys.is_empty() {
+            return Err(OAuthError::UnknownError("At least one session key is required".to_string()));
+        }
+
+        let mut session_keys = Vec::new();
+        for key in keys.into_iter() {
+            if key.len() != KEY_SIZE {
+                return Err(OAuthError::UnknownError("Session keys must be 256-bit".to_string()));
+            }
+            session_keys.push(SessionKey {
+                kid: try!(key_id(&key)),
+                key: key,
+            });
+        }
+
+        Ok(SessionCodec { keys: session_keys })
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<SessionCodec, OAuthError> {
+        let mut keys = Vec::new();
+        for key in settings.session_keys.iter() {
+            keys.push(try!(decode_key(key.expose())));
+        }
+        SessionCodec::new(keys)
+    }
+
+    pub fn seal(&self, name: &str, plaintext: &[u8]) -> Result<String, OAuthError> {
+        let key = &self.keys[0];
+        let mut iv = vec![0u8; IV_SIZE];
+        try!(rand_bytes(&mut iv));
+
+        let aad = format!("{}.{}", name, key.kid);
+        let mut tag = vec![0u8; TAG_SIZE];
+        let ciphertext = try!(encrypt_aead(Cipher::aes_256_gcm(), &key.key, Some(&iv),
+                                           aad.as_bytes(), plaintext, &mut tag));
+
+        Ok([key.kid.clone(), encode(&iv), encode(&ciphertext), encode(&tag)].join("."))
+    }
+
+    // None when the value is malformed, sealed with an unknown key or fails
+    // authentication, all of which mean it cannot be trusted.
+    pub fn open(&self, name: &str, value: &str) -> Option<Vec<u8>> {
+        let parts: Vec<&str> = value.split('.').collect();
+        if parts.len() != 4 {
+            return None;
+        }
+
+        let key = match self.keys.iter().find(|key| key.kid == parts[0]) {
+            Some(key) => key,
+            None => return None,
+        };
+        let (iv, ciphertext, tag) = match (parts[1].from_base64(), parts[2].from_base64(), parts[3].from_base64()) {
+            (Ok(iv), Ok(ciphertext), Ok(tag)) => (iv, ciphertext, tag),
+            _ => return None,
+        };
+        // GCM takes shorter tags too, which would make forgeries cheap
+        if iv.len() != IV_SIZE || tag.len() != TAG_SIZE {
+            return None;
+        }
+
+        let aad = format!("{}.{}", name, key.kid);
+        decrypt_aead(Cipher::aes_256_gcm(), &key.key, Some(&iv),
+                     aad.as_bytes(), &ciphertext, &tag).ok()
+    }
+
+    // Whether a value was sealed with the current key, so values sealed
+    // with an older one can be resealed.
+    pub fn is_current(&self, value: &str) -> bool {
+        value.split('.').next() == Some(self.keys[0].kid.as_str())
+    }
+}
+
+//--------------------------------------------------------------------------
+// Chunking
+//--------------------------------------------------------------------------
+
+// Splits a sealed value into cookie sized pieces. Sealed values are ASCII so
+// splitting on bytes is safe.
+pub fn split(value: &str) -> Result<Vec<String>, OAuthError> {
+    if value.len() > CHUNK_SIZE * MAX_CHUNKS {
+        return Err(OAuthError::UnknownError(format!("Session of {} bytes does not fit in {} cookies", value.len(), MAX_CHUNKS)));
+    }
+
+    Ok(value.as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect())
+}
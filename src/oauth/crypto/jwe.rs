@@ -0,0 +1,238 @@
+use openssl::aes::{AesKey, wrap_key, unwrap_key};
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+use openssl::symm::{Cipher, encrypt_aead, decrypt_aead};
+use rustc_serialize::base64::{self, ToBase64, FromBase64};
+use serde_json;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use oauth::secret::{self, Secret, SecretBytes};
+use error::OAuthError;
+
+// Compact JWE (RFC 7516) used to keep secret keys encrypted at rest. The
+// content is always encrypted with A256GCM under a random key, which is
+// wrapped either with a key derived from a passphrase (PBES2-HS256+A128KW)
+// or directly with a 256-bit key encryption key (A256KW).
+
+pub const PBES2_ALGORITHM: &'static str = "PBES2-HS256+A128KW";
+pub const KEK_ALGORITHM: &'static str = "A256KW";
+pub const ENCRYPTION: &'static str = "A256GCM";
+
+const PBES2_ITERATIONS: u32 = 100_000;
+// p2c comes from the file, so a crafted one could stall startup or reload
+const MIN_PBES2_ITERATIONS: u32 = 1_000;
+const MAX_PBES2_ITERATIONS: u32 = 1_000_000;
+const SALT_SIZE: usize = 16;
+const CEK_SIZE: usize = 32;
+const IV_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const KEK_SIZE: usize = 32;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    enc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2s: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2c: Option<u32>,
+}
+
+//--------------------------------------------------------------------------
+// KeyUnlock
+//--------------------------------------------------------------------------
+
+// What unlocks an encrypted secret key, zeroed when dropped
+#[derive(Clone, Debug)]
+pub enum KeyUnlock {
+    Passphrase(Secret),
+    Kek(SecretBytes),
+}
+
+fn read_file(path: &str) -> Result<String, OAuthError> {
+    let mut file = try!(File::open(path));
+    let mut contents = String::new();
+    let _ = try!(file.read_to_string(&mut contents));
+    Ok(contents.trim().to_string())
+}
+
+impl KeyUnlock {
+
+    // Reads SECRET_KEY_PASSPHRASE (which may use file: / env: indirection),
+    // SECRET_KEY_PASSPHRASE_FILE or SECRET_KEY_KEK_FILE (a base64url encoded
+    // 256-bit key), in that order.
+    pub fn from_env() -> Result<Option<KeyUnlock>, OAuthError> {
+        if let Ok(passphrase) = env::var("SECRET_KEY_PASSPHRASE") {
+            let passphrase = try!(secret::resolve(passphrase)
+                .map_err(|err| OAuthError::UnknownError(format!("SECRET_KEY_PASSPHRASE: {}", err))));
+            return Ok(Some(KeyUnlock::Passphrase(Secret::new(passphrase))));
+        }
+
+        if let Ok(path) = env::var("SECRET_KEY_PASSPHRASE_FILE") {
+            return Ok(Some(KeyUnlock::Passphrase(Secret::new(try!(read_file(&path))))));
+        }
+
+        if let Ok(path) = env::var("SECRET_KEY_KEK_FILE") {
+            let kek = SecretBytes::new(try!(decode(&try!(read_file(&path)))));
+            if kek.expose().len() != KEK_SIZE {
+                return Err(OAuthError::UnknownError(format!("{} must contain a 256-bit key", path)));
+            }
+            return Ok(Some(KeyUnlock::Kek(kek)));
+        }
+
+        Ok(None)
+    }
+}
+
+// A fresh base64url encoded key encryption key
+pub fn generate_kek() -> Result<String, OAuthError> {
+    Ok(encode(&try!(random(KEK_SIZE))))
+}
+
+//--------------------------------------------------------------------------
+// Utilities
+//--------------------------------------------------------------------------
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.to_base64(base64::URL_SAFE)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, OAuthError> {
+    value.from_base64()
+        .map_err(|err| OAuthError::UnknownError(format!("Invalid JWE encoding: {}", err)))
+}
+
+fn random(size: usize) -> Result<Vec<u8>, OAuthError> {
+    let mut bytes = vec![0u8; size];
+    try!(rand_bytes(&mut bytes));
+    Ok(bytes)
+}
+
+// PBES2 salt input is UTF8(alg) || 0x00 || p2s (RFC 7518 section 4.8.1.1)
+fn pbes2_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<Vec<u8>, OAuthError> {
+    let mut salt_input = PBES2_ALGORITHM.as_bytes().to_vec();
+    salt_input.push(0);
+    salt_input.extend_from_slice(salt);
+
+    let mut key = vec![0u8; 16];
+    try!(pbkdf2_hmac(passphrase.as_bytes(), &salt_input, iterations as usize,
+                     MessageDigest::sha256(), &mut key));
+    Ok(key)
+}
+
+fn wrap(kek: &[u8], cek: &[u8]) -> Result<Vec<u8>, OAuthError> {
+    let key = try!(AesKey::new_encrypt(kek)
+        .map_err(|_| OAuthError::UnknownError("Invalid key encryption key".to_string())));
+    let mut wrapped = vec![0u8; cek.len() + 8];
+    try!(wrap_key(&key, None, &mut wrapped, cek)
+        .map_err(|_| OAuthError::UnknownError("Unable to wrap key".to_string())));
+    Ok(wrapped)
+}
+
+fn unwrap(kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>, OAuthError> {
+    if wrapped.len() < 16 {
+        return Err(OAuthError::UnknownError("Invalid wrapped key".to_string()));
+    }
+    let key = try!(AesKey::new_decrypt(kek)
+        .map_err(|_| OAuthError::UnknownError("Invalid key encryption key".to_string())));
+    let mut cek = vec![0u8; wrapped.len() - 8];
+    try!(unwrap_key(&key, None, &mut cek, wrapped)
+        .map_err(|_| OAuthError::UnknownError("Unable to unwrap key, wrong passphrase?".to_string())));
+    Ok(cek)
+}
+
+//--------------------------------------------------------------------------
+// Encryption
+//--------------------------------------------------------------------------
+
+pub fn encrypt(plaintext: &[u8], unlock: &KeyUnlock) -> Result<String, OAuthError> {
+    let cek = try!(random(CEK_SIZE));
+
+    let (header, encrypted_key) = match *unlock {
+        KeyUnlock::Passphrase(ref passphrase) => {
+            let salt = try!(random(SALT_SIZE));
+            let kek = try!(pbes2_key(passphrase.expose(), &salt, PBES2_ITERATIONS));
+            let header = Header {
+                alg: PBES2_ALGORITHM.to_string(),
+                enc: ENCRYPTION.to_string(),
+                cty: Some("jwk+json".to_string()),
+                p2s: Some(encode(&salt)),
+                p2c: Some(PBES2_ITERATIONS),
+            };
+            (header, try!(wrap(&kek, &cek)))
+        },
+
+        KeyUnlock::Kek(ref kek) => {
+            let header = Header {
+                alg: KEK_ALGORITHM.to_string(),
+                enc: ENCRYPTION.to_string(),
+                cty: Some("jwk+json".to_string()),
+                p2s: None,
+                p2c: None,
+            };
+            (header, try!(wrap(kek.expose(), &cek)))
+        },
+    };
+
+    let protected = encode(try!(serde_json::to_string(&header)).as_bytes());
+    let iv = try!(random(IV_SIZE));
+    let mut tag = vec![0u8; TAG_SIZE];
+    let ciphertext = try!(encrypt_aead(Cipher::aes_256_gcm(), &cek, Some(&iv),
+                                       protected.as_bytes(), plaintext, &mut tag));
+
+    Ok([protected, encode(&encrypted_key), encode(&iv), encode(&ciphertext), encode(&tag)].join("."))
+}
+
+pub fn decrypt(token: &str, unlock: &KeyUnlock) -> Result<Vec<u8>, OAuthError> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    if parts.len() != 5 {
+        return Err(OAuthError::UnknownError("Expected a compact JWE with five segments".to_string()));
+    }
+
+    let header: Header = try!(serde_json::from_slice(&try!(decode(parts[0]))));
+    if header.enc != ENCRYPTION {
+        return Err(OAuthError::UnknownError(format!("Unsupported enc {}", header.enc)));
+    }
+
+    let encrypted_key = try!(decode(parts[1]));
+    let cek = match (header.alg.as_str(), unlock) {
+        (PBES2_ALGORITHM, &KeyUnlock::Passphrase(ref passphrase)) => {
+            let salt = match header.p2s {
+                Some(ref p2s) => try!(decode(p2s)),
+                None => return Err(OAuthError::UnknownError("PBES2 header has no p2s".to_string())),
+            };
+            let iterations = header.p2c.unwrap_or(PBES2_ITERATIONS);
+            if iterations < MIN_PBES2_ITERATIONS || iterations > MAX_PBES2_ITERATIONS {
+                return Err(OAuthError::UnknownError(format!("PBES2 p2c {} outside {}..{}", iterations, MIN_PBES2_ITERATIONS, MAX_PBES2_ITERATIONS)));
+            }
+            let kek = try!(pbes2_key(passphrase.expose(), &salt, iterations));
+            try!(unwrap(&kek, &encrypted_key))
+        },
+
+        (KEK_ALGORITHM, &KeyUnlock::Kek(ref kek)) => try!(unwrap(kek.expose(), &encrypted_key)),
+
+        (alg, _) => {
+            return Err(OAuthError::UnknownError(format!("Key is protected with {}, which the configured unlock cannot open", alg)));
+        },
+    };
+
+    let iv = try!(decode(parts[2]));
+    let ciphertext = try!(decode(parts[3]));
+    let tag = try!(decode(parts[4]));
+    if iv.len() != IV_SIZE || tag.len() != TAG_SIZE {
+        return Err(OAuthError::UnknownError("JWE iv or tag has the wrong length".to_string()));
+    }
+    let plaintext = try!(decrypt_aead(Cipher::aes_256_gcm(), &cek, Some(&iv),
+                                      parts[0].as_bytes(), &ciphertext, &tag)
+        .map_err(|_| OAuthError::UnknownError("Secret key failed authentication".to_string())));
+    Ok(plaintext)
+}
+
+// Encrypted files hold a compact JWE, plain ones a JSON object
+pub fn is_encrypted(contents: &str) -> bool {
+    !contents.trim_left().starts_with('{')
+}
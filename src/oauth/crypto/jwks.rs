@@ -0,0 +1,140 @@
+use reqwest;
+use reqwest::StatusCode;
+use reqwest::header::{CacheControl, CacheDirective};
+use serde_json::{self, Value};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use error::OAuthError;
+use oauth::client::ProviderClient;
+use super::PublicKey;
+
+// Used when the provider sends no Cache-Control max-age
+const DEFAULT_TTL: u64 = 3600;
+
+// An unknown kid triggers a refresh at most this often, so that garbage
+// tokens cannot make us hammer the provider.
+pub const MIN_REFRESH_INTERVAL: u64 = 60;
+
+//--------------------------------------------------------------------------
+// RemoteKeySet
+//--------------------------------------------------------------------------
+
+struct Cached {
+    keys: Vec<PublicKey>,
+    fetched_at: Instant,
+    expires_at: Instant,
+}
+
+// The provider's JWK Set, cached according to its HTTP cache headers and
+// refreshed early when a token names a kid we have not seen yet.
+pub struct RemoteKeySet {
+    url: String,
+    cache: RwLock<Option<Cached>>,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Value>,
+}
+
+// max-age from Cache-Control, zero for no-cache / no-store
+pub fn cache_ttl(headers: &reqwest::header::Headers) -> Duration {
+    let directives = match headers.get::<CacheControl>() {
+        Some(&CacheControl(ref directives)) => directives.clone(),
+        None => return Duration::from_secs(DEFAULT_TTL),
+    };
+
+    let mut ttl = Duration::from_secs(DEFAULT_TTL);
+    for directive in directives.iter() {
+        match *directive {
+            CacheDirective::NoCache | CacheDirective::NoStore => return Duration::from_secs(0),
+            CacheDirective::MaxAge(secs) => ttl = Duration::from_secs(secs as u64),
+            _ => {},
+        }
+    }
+    ttl
+}
+
+// Whether a set fetched at fetched_at should be fetched again: always once
+// it has expired, and for a kid it lacks no more than once a minute.
+pub fn should_refresh(now: Instant, fetched_at: Instant, expires_at: Instant, has_kid: bool) -> bool {
+    if now >= expires_at {
+        return true;
+    }
+    !has_kid && now.duration_since(fetched_at) >= Duration::from_secs(MIN_REFRESH_INTERVAL)
+}
+
+impl RemoteKeySet {
+
+    pub fn new(url: String) -> RemoteKeySet {
+        RemoteKeySet {
+            url: url,
+            cache: RwLock::new(None),
+        }
+    }
+
+    #[inline]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn fetch(&self, client: &ProviderClient) -> Result<Cached, OAuthError> {
+        let mut rsp = try!(client.get(&self.url));
+
+        if *rsp.status() != StatusCode::Ok {
+            return Err(OAuthError::JWTError(format!("JWKS request to {} failed with {}", self.url, rsp.status())));
+        }
+
+        let ttl = cache_ttl(rsp.headers());
+        let set: JwkSet = try!(rsp.json());
+
+        // Skip keys we cannot use rather than rejecting the whole set
+        let keys = set.keys.into_iter()
+            .filter_map(|key| serde_json::from_value::<PublicKey>(key).ok())
+            .collect();
+
+        let now = Instant::now();
+        Ok(Cached {
+            keys: keys,
+            fetched_at: now,
+            expires_at: now + ttl,
+        })
+    }
+
+    // The cached key for a kid, and whether the set may be fetched again
+    fn lookup(&self, kid: &str) -> (Option<PublicKey>, bool) {
+        let cache = self.cache.read().unwrap();
+        match *cache {
+            Some(ref cached) => {
+                let now = Instant::now();
+                let key = match now < cached.expires_at {
+                    true => cached.keys.iter().find(|key| key.kid() == kid).cloned(),
+                    false => None,
+                };
+                let may_refresh = should_refresh(now, cached.fetched_at, cached.expires_at, key.is_some());
+                (key, may_refresh)
+            },
+            None => (None, true),
+        }
+    }
+
+    // Finds the key for a kid, refreshing the set when it has expired or
+    // does not contain the kid.
+    pub fn find(&self, client: &ProviderClient, kid: &str) -> Result<PublicKey, OAuthError> {
+        let (key, may_refresh) = self.lookup(kid);
+        if let Some(key) = key {
+            return Ok(key);
+        }
+
+        if may_refresh {
+            let fetched = try!(self.fetch(client));
+            let key = fetched.keys.iter().find(|key| key.kid() == kid).cloned();
+            *self.cache.write().unwrap() = Some(fetched);
+            if let Some(key) = key {
+                return Ok(key);
+            }
+        }
+
+        Err(OAuthError::JWTError(format!("Unknown kid {}", kid)))
+    }
+}
@@ -0,0 +1,159 @@
+use rustc_serialize::base64::{self, ToBase64, FromBase64};
+use serde::{Serialize, Deserialize};
+use serde_json::{self, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+use oauth::settings::Settings;
+use error::OAuthError;
+use super::{SigningKey, PublicKey};
+
+// Tolerated clock skew when checking exp / nbf (seconds)
+pub const LEEWAY: u64 = 60;
+
+//--------------------------------------------------------------------------
+// Header
+//--------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Header {
+    pub alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+}
+
+//--------------------------------------------------------------------------
+// SessionClaims
+//--------------------------------------------------------------------------
+
+// Asserts to downstream services that `sub` logged in through FxA
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub email: String,
+    pub scope: String,
+}
+
+impl SessionClaims {
+    pub fn new(settings: &Settings, uid: String, email: String, scopes: &[String]) -> SessionClaims {
+        let iat = timestamp();
+        SessionClaims {
+            iss: settings.jwt_issuer.clone(),
+            sub: uid,
+            aud: settings.jwt_audience.clone(),
+            iat: iat,
+            exp: iat + settings.jwt_ttl,
+            email: email,
+            scope: scopes.join(" "),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Encoding
+//--------------------------------------------------------------------------
+
+// Seconds since the unix epoch
+pub fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn encode_part<T: Serialize>(part: &T) -> Result<String, OAuthError> {
+    let json = try!(serde_json::to_string(part));
+    Ok(json.as_bytes().to_base64(base64::URL_SAFE))
+}
+
+fn decode_part(part: &str) -> Result<Vec<u8>, OAuthError> {
+    part.from_base64()
+        .map_err(|err| OAuthError::JWTError(format!("Invalid base64: {}", err)))
+}
+
+fn split(token: &str) -> Result<(&str, &str, &str), OAuthError> {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 {
+        return Err(OAuthError::JWTError("Expected three segments".to_string()));
+    }
+    Ok((parts[0], parts[1], parts[2]))
+}
+
+// Signs the claims with the key's algorithm, naming the key in the header
+pub fn encode<T: Serialize>(claims: &T, key: &SigningKey) -> Result<String, OAuthError> {
+    let header = Header {
+        alg: key.secret_key.algorithm().name().to_string(),
+        typ: Some("JWT".to_string()),
+        kid: Some(key.kid.clone()),
+    };
+
+    let input = format!("{}.{}", try!(encode_part(&header)), try!(encode_part(claims)));
+
+    let signature = try!(key.secret_key.sign(input.as_bytes()));
+
+    Ok(format!("{}.{}", input, signature.to_base64(base64::URL_SAFE)))
+}
+
+//--------------------------------------------------------------------------
+// Decoding
+//--------------------------------------------------------------------------
+
+// Reads the header without verifying anything, e.g. to pick a key by kid
+pub fn decode_header(token: &str) -> Result<Header, OAuthError> {
+    let (header, _, _) = try!(split(token));
+    let header: Header = try!(serde_json::from_slice(&try!(decode_part(header))));
+    Ok(header)
+}
+
+fn check_time(claims: &Value) -> Result<(), OAuthError> {
+    let now = timestamp();
+
+    if let Some(exp) = claims.get("exp") {
+        match exp.as_u64() {
+            Some(exp) if exp + LEEWAY > now => {},
+            Some(_) => return Err(OAuthError::JWTError("Token expired".to_string())),
+            None => return Err(OAuthError::JWTError("Invalid exp claim".to_string())),
+        }
+    }
+
+    if let Some(nbf) = claims.get("nbf") {
+        match nbf.as_u64() {
+            Some(nbf) if nbf <= now + LEEWAY => {},
+            Some(_) => return Err(OAuthError::JWTError("Token not yet valid".to_string())),
+            None => return Err(OAuthError::JWTError("Invalid nbf claim".to_string())),
+        }
+    }
+
+    Ok(())
+}
+
+// Verifies a signature made by the given key along with exp / nbf, then
+// deserializes the claims. The alg header must match the key so a token can
+// never pick a weaker algorithm.
+pub fn decode<T: Deserialize>(token: &str, key: &PublicKey) -> Result<T, OAuthError> {
+    let (header_part, claims_part, signature_part) = try!(split(token));
+
+    let header = try!(decode_header(token));
+    if header.alg != key.algorithm().name() {
+        return Err(OAuthError::JWTError(format!("Unexpected alg {}", header.alg)));
+    }
+    if let Some(ref kid) = header.kid {
+        if kid != key.kid() {
+            return Err(OAuthError::JWTError(format!("Unknown kid {}", kid)));
+        }
+    }
+
+    let input = format!("{}.{}", header_part, claims_part);
+    let signature = try!(decode_part(signature_part));
+
+    if !try!(key.verify(input.as_bytes(), &signature)) {
+        return Err(OAuthError::JWTError("Invalid signature".to_string()));
+    }
+
+    let claims: Value = try!(serde_json::from_slice(&try!(decode_part(claims_part))));
+    try!(check_time(&claims));
+
+    let claims: T = try!(serde_json::from_value(claims));
+    Ok(claims)
+}
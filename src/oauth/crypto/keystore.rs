@@ -0,0 +1,285 @@
+use serde_json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use oauth::settings::Settings;
+use error::OAuthError;
+use super::jwt::timestamp;
+use super::{generate, encode_secret_key, read_key_file, read_secret_key_file, read_public_key, read_signing_key};
+use super::{Encodable, KeySpec, PublicKey, SecretKey, SigningKey};
+use super::jwe::KeyUnlock;
+
+// Keystore directory layout:
+//
+//   keys.json          manifest listing every key and its rotation status
+//   <kid>.public.json  public JWK
+//   <kid>.secret.json  secret JWK, or a compact JWE when encrypted
+//
+// where kid is the RFC 7638 thumbprint of the key.
+//
+// A key is created as `next` and published in the JWKS without signing
+// anything. Rotating promotes it to `current` once it has been published for
+// the grace period, so every verifier has had time to fetch it. The previous
+// `current` key becomes `retiring` and stays published for another grace
+// period so that tokens it signed can still be verified.
+
+const MANIFEST: &'static str = "keys.json";
+
+// Secret keys are readable by their owner only
+const SECRET_MODE: u32 = 0o600;
+const PUBLIC_MODE: u32 = 0o644;
+
+//--------------------------------------------------------------------------
+// Manifest
+//--------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum KeyStatus {
+    #[serde(rename = "next")]
+    Next,
+    #[serde(rename = "current")]
+    Current,
+    #[serde(rename = "retiring")]
+    Retiring,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyEntry {
+    pub kid: String,
+    pub status: KeyStatus,
+    // Seconds since the unix epoch
+    pub created_at: u64,
+    #[serde(default)]
+    pub activated_at: Option<u64>,
+    #[serde(default)]
+    pub retired_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Manifest {
+    pub keys: Vec<KeyEntry>,
+}
+
+impl Encodable for Manifest {}
+
+impl Manifest {
+    fn find(&self, status: KeyStatus) -> Option<&KeyEntry> {
+        self.keys.iter().find(|entry| entry.status == status)
+    }
+}
+
+pub fn public_key_path(dir: &Path, kid: &str) -> PathBuf {
+    dir.join(format!("{}.public.json", kid))
+}
+
+pub fn secret_key_path(dir: &Path, kid: &str) -> PathBuf {
+    dir.join(format!("{}.secret.json", kid))
+}
+
+pub fn read_manifest(dir: &Path) -> Result<Manifest, OAuthError> {
+    let path = dir.join(MANIFEST);
+    if !path.exists() {
+        return Ok(Manifest::default());
+    }
+    let mut file = try!(File::open(path));
+    let mut contents = String::new();
+    let _ = try!(file.read_to_string(&mut contents));
+    let manifest: Manifest = try!(serde_json::from_str(&contents));
+    Ok(manifest)
+}
+
+fn write_file(path: &Path, contents: &str, mode: u32) -> Result<(), OAuthError> {
+    let tmp_path = path.with_extension("tmp");
+    // A leftover from an interrupted write would keep its mode
+    let _ = fs::remove_file(&tmp_path);
+    {
+        let mut file = try!(OpenOptions::new().write(true).create_new(true).mode(mode).open(&tmp_path));
+        try!(file.write_all(contents.as_bytes()));
+        try!(file.sync_all());
+    }
+    try!(fs::rename(&tmp_path, path));
+    Ok(())
+}
+
+fn write_manifest(dir: &Path, manifest: &Manifest) -> Result<(), OAuthError> {
+    write_file(&dir.join(MANIFEST), &try!(manifest.encode()), PUBLIC_MODE)
+}
+
+//--------------------------------------------------------------------------
+// Rotation
+//--------------------------------------------------------------------------
+
+// Writes <kid>.secret.json and <kid>.public.json, without adding the key to
+// the manifest
+pub fn write_key_pair(dir: &Path, secret_key: &SecretKey, public_key: &PublicKey, unlock: Option<&KeyUnlock>) -> Result<(), OAuthError> {
+    let kid = public_key.kid();
+    try!(write_file(&secret_key_path(dir, kid), &try!(encode_secret_key(secret_key, unlock)), SECRET_MODE));
+    try!(write_file(&public_key_path(dir, kid), &try!(public_key.encode()), PUBLIC_MODE));
+    Ok(())
+}
+
+// Generates a key pair, writes both halves to the keystore and returns its
+// manifest entry.
+fn create_key(dir: &Path, status: KeyStatus, spec: KeySpec, unlock: Option<&KeyUnlock>) -> Result<KeyEntry, OAuthError> {
+    let (secret_key, public_key) = try!(generate(spec));
+    let created_at = timestamp();
+
+    let kid = public_key.kid().to_string();
+    try!(write_key_pair(dir, &secret_key, &public_key, unlock));
+
+    Ok(KeyEntry {
+        kid: kid,
+        status: status,
+        created_at: created_at,
+        activated_at: match status {
+            KeyStatus::Current => Some(created_at),
+            _ => None,
+        },
+        retired_at: None,
+    })
+}
+
+fn is_expired(entry: &KeyEntry, grace_period: u64, now: u64) -> bool {
+    match (entry.status, entry.retired_at) {
+        (KeyStatus::Retiring, Some(retired_at)) => retired_at + grace_period <= now,
+        _ => false,
+    }
+}
+
+// Creates a keystore with a current key and a published next key
+pub fn init(dir: &Path, spec: KeySpec, unlock: Option<&KeyUnlock>) -> Result<Manifest, OAuthError> {
+    let mut manifest = try!(read_manifest(dir));
+    if !manifest.keys.is_empty() {
+        return Err(OAuthError::UnknownError(format!("Keystore {:?} already initialised", dir)));
+    }
+
+    try!(fs::create_dir_all(dir));
+    manifest.keys.push(try!(create_key(dir, KeyStatus::Current, spec, unlock)));
+    manifest.keys.push(try!(create_key(dir, KeyStatus::Next, spec, unlock)));
+    try!(write_manifest(dir, &manifest));
+    Ok(manifest)
+}
+
+// Runs one step of the rotation workflow:
+//
+// - retiring keys past the grace period are deleted
+// - the next key, once published for the grace period, starts signing
+// - the former current key is kept published as retiring
+// - a fresh next key is published, which may use a different algorithm
+pub fn rotate(dir: &Path, grace_period: u64, spec: KeySpec, unlock: Option<&KeyUnlock>) -> Result<Manifest, OAuthError> {
+    let mut manifest = try!(read_manifest(dir));
+    let now = timestamp();
+
+    for entry in manifest.keys.iter().filter(|entry| is_expired(entry, grace_period, now)) {
+        let _ = fs::remove_file(secret_key_path(dir, &entry.kid));
+        let _ = fs::remove_file(public_key_path(dir, &entry.kid));
+    }
+    manifest.keys.retain(|entry| !is_expired(entry, grace_period, now));
+
+    let ready = match manifest.find(KeyStatus::Next) {
+        Some(next) => next.created_at + grace_period <= now,
+        None => false,
+    };
+
+    if ready {
+        for entry in manifest.keys.iter_mut() {
+            match entry.status {
+                KeyStatus::Current => {
+                    entry.status = KeyStatus::Retiring;
+                    entry.retired_at = Some(now);
+                },
+                KeyStatus::Next => {
+                    entry.status = KeyStatus::Current;
+                    entry.activated_at = Some(now);
+                },
+                KeyStatus::Retiring => {},
+            }
+        }
+    }
+
+    if manifest.find(KeyStatus::Next).is_none() {
+        manifest.keys.push(try!(create_key(dir, KeyStatus::Next, spec, unlock)));
+    }
+
+    try!(write_manifest(dir, &manifest));
+    Ok(manifest)
+}
+
+//--------------------------------------------------------------------------
+// KeyStore
+//--------------------------------------------------------------------------
+
+// The keys served at /.well-known/jwks.json and the one signing assertions
+pub struct KeyStore {
+    published: Vec<PublicKey>,
+    signing_key: SigningKey,
+}
+
+impl KeyStore {
+
+    // Loads the keystore directory, skipping retiring keys past the grace
+    // period which have not been deleted yet.
+    pub fn open(dir: &Path, grace_period: u64, unlock: Option<&KeyUnlock>) -> Result<KeyStore, OAuthError> {
+        let manifest = try!(read_manifest(dir));
+        let now = timestamp();
+
+        let current = match manifest.find(KeyStatus::Current) {
+            Some(current) => current.clone(),
+            None => return Err(OAuthError::UnknownError(format!("Keystore {:?} has no current key", dir))),
+        };
+
+        let mut published = Vec::new();
+        for entry in manifest.keys.iter().filter(|entry| !is_expired(entry, grace_period, now)) {
+            let public_key: PublicKey = try!(read_key_file(&public_key_path(dir, &entry.kid)));
+            published.push(public_key);
+        }
+
+        let secret_key = try!(read_secret_key_file(&secret_key_path(dir, &current.kid), unlock));
+
+        Ok(KeyStore {
+            published: published,
+            signing_key: SigningKey {
+                kid: current.kid,
+                secret_key: secret_key,
+            },
+        })
+    }
+
+    // Without a keystore directory the single PUBLIC_KEY_PATH /
+    // SECRET_KEY_PATH pair is both published and used for signing.
+    pub fn from_settings(settings: &Settings) -> Result<KeyStore, OAuthError> {
+        match settings.keystore_path {
+            Some(ref dir) => {
+                KeyStore::open(Path::new(dir), settings.key_grace_period, settings.secret_key_unlock.as_ref())
+            },
+            None => {
+                let public_key = try!(read_public_key(settings.clone()));
+                let signing_key = try!(read_signing_key(settings.clone()));
+                Ok(KeyStore {
+                    published: vec![public_key],
+                    signing_key: signing_key,
+                })
+            },
+        }
+    }
+
+    #[inline]
+    pub fn published(&self) -> &[PublicKey] {
+        &self.published
+    }
+
+    #[inline]
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.signing_key
+    }
+
+    // The public half of the signing key
+    pub fn current(&self) -> Option<&PublicKey> {
+        self.find(&self.signing_key.kid)
+    }
+
+    pub fn find(&self, kid: &str) -> Option<&PublicKey> {
+        self.published.iter().find(|key| key.kid() == kid)
+    }
+}
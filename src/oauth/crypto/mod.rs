@@ -0,0 +1,583 @@
+pub mod aead;
+pub mod jwe;
+pub mod jwks;
+pub mod jwt;
+pub mod keystore;
+
+use openssl::bn::{BigNum, BigNumContext, BigNumRef};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private, Public};
+use openssl::rsa::Rsa;
+use openssl::sign::{Signer, Verifier};
+use rustc_serialize::base64::{self, ToBase64, FromBase64};
+use serde_json;
+use serde_json::Value;
+use serde;
+use oauth::settings::Settings;
+use error::OAuthError;
+use self::jwe::KeyUnlock;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Size in bytes of P-256 coordinates and scalars
+const P256_SIZE: usize = 32;
+
+//--------------------------------------------------------------------------
+// Algorithms
+//--------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    RS256,
+    ES256,
+    EdDSA,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::RS256 => "RS256",
+            Algorithm::ES256 => "ES256",
+            Algorithm::EdDSA => "EdDSA",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name {
+            "RS256" => Some(Algorithm::RS256),
+            "ES256" => Some(Algorithm::ES256),
+            "EdDSA" => Some(Algorithm::EdDSA),
+            _ => None,
+        }
+    }
+}
+
+// What generate_keypair should produce
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeySpec {
+    RSA(u32),
+    P256,
+    Ed25519,
+}
+
+impl KeySpec {
+    pub fn from_algorithm(algorithm: Algorithm, rsa_bits: u32) -> Result<KeySpec, OAuthError> {
+        match algorithm {
+            Algorithm::RS256 => match rsa_bits {
+                2048 | 3072 | 4096 => Ok(KeySpec::RSA(rsa_bits)),
+                _ => Err(OAuthError::UnknownError(format!("Unsupported RSA size {}", rsa_bits))),
+            },
+            Algorithm::ES256 => Ok(KeySpec::P256),
+            Algorithm::EdDSA => Ok(KeySpec::Ed25519),
+        }
+    }
+}
+
+impl Default for KeySpec {
+    fn default() -> KeySpec {
+        KeySpec::RSA(2048)
+    }
+}
+
+//--------------------------------------------------------------------------
+// Keys
+//--------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kty")]
+pub enum SecretKey {
+    RSA {
+        n: String,
+        e: String,
+        d: String,
+        p: String,
+        q: String,
+        dp: String,
+        dq: String,
+        qi: String,
+    },
+    EC {
+        crv: String,
+        x: String,
+        y: String,
+        d: String,
+    },
+    OKP {
+        crv: String,
+        x: String,
+        d: String,
+    },
+}
+
+pub trait Encodable where Self: serde::Serialize {
+    fn encode(&self) -> Result<String, OAuthError> {
+        let encoded = try!(serde_json::to_string(self));
+        Ok(encoded)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kty")]
+pub enum PublicKey {
+    RSA {
+        kid: String,
+        n: String,
+        e: String,
+    },
+    EC {
+        kid: String,
+        crv: String,
+        x: String,
+        y: String,
+    },
+    OKP {
+        kid: String,
+        crv: String,
+        x: String,
+    },
+}
+
+impl Encodable for SecretKey {}
+impl Encodable for PublicKey {}
+
+// A secret key together with the kid of its public half, which is what
+// goes into the header of the JWTs it signs.
+pub struct SigningKey {
+    pub kid: String,
+    pub secret_key: SecretKey,
+}
+
+pub fn read_key_file<T: serde::Deserialize>(path: &Path) -> Result<T, OAuthError> {
+    let mut file = try!(File::open(path));
+    let mut contents = String::new();
+    let _ = try!(file.read_to_string(&mut contents));
+    let result: T = try!(serde_json::from_str(&contents));
+    Ok(result)
+}
+
+// Reads a secret key stored either as plain JSON or as a compact JWE, which
+// requires the passphrase or key encryption key to unlock it.
+pub fn read_secret_key_file(path: &Path, unlock: Option<&KeyUnlock>) -> Result<SecretKey, OAuthError> {
+    let mut file = try!(File::open(path));
+    let mut contents = String::new();
+    let _ = try!(file.read_to_string(&mut contents));
+
+    if !jwe::is_encrypted(&contents) {
+        let result: SecretKey = try!(serde_json::from_str(&contents));
+        return Ok(result);
+    }
+
+    match unlock {
+        Some(unlock) => {
+            let plaintext = try!(jwe::decrypt(&contents, unlock));
+            let result: SecretKey = try!(serde_json::from_slice(&plaintext));
+            Ok(result)
+        },
+        None => Err(OAuthError::UnknownError(format!("{:?} is encrypted but no passphrase or KEK was configured", path))),
+    }
+}
+
+// Serializes a secret key, encrypted when an unlock is configured
+pub fn encode_secret_key(secret_key: &SecretKey, unlock: Option<&KeyUnlock>) -> Result<String, OAuthError> {
+    let json = try!(secret_key.encode());
+    match unlock {
+        Some(unlock) => jwe::encrypt(json.as_bytes(), unlock),
+        None => Ok(json),
+    }
+}
+
+pub fn read_public_key(settings: Settings) -> Result<PublicKey, OAuthError> {
+    read_key_file(Path::new(&settings.public_key_path))
+}
+
+pub fn read_secret_key(settings: Settings) -> Result<SecretKey, OAuthError> {
+    read_secret_key_file(Path::new(&settings.secret_key_path), settings.secret_key_unlock.as_ref())
+}
+
+pub fn read_signing_key(settings: Settings) -> Result<SigningKey, OAuthError> {
+    let public_key = try!(read_public_key(settings.clone()));
+    let secret_key = try!(read_secret_key(settings));
+
+    if try!(public_key.thumbprint()) != try!(secret_key.thumbprint()) {
+        return Err(OAuthError::UnknownError("Secret key does not match public key".to_string()));
+    }
+
+    Ok(SigningKey {
+        kid: public_key.kid().to_string(),
+        secret_key: secret_key,
+    })
+}
+
+//--------------------------------------------------------------------------
+// Thumbprints (RFC 7638)
+//--------------------------------------------------------------------------
+
+// Members hashed for each key type, in lexicographic order
+fn thumbprint_members(kty: &str) -> Option<&'static [&'static str]> {
+    match kty {
+        "RSA" => Some(&["e", "kty", "n"]),
+        "EC" => Some(&["crv", "kty", "x", "y"]),
+        "OKP" => Some(&["crv", "kty", "x"]),
+        _ => None,
+    }
+}
+
+// Base64url SHA-256 thumbprint of any JWK, public or secret. Only the
+// required public members are hashed so both halves of a pair agree.
+pub fn thumbprint(jwk: &Value) -> Result<String, OAuthError> {
+    let kty = match jwk.get("kty").and_then(|kty| kty.as_str()) {
+        Some(kty) => kty,
+        None => return Err(OAuthError::UnknownError("JWK has no kty".to_string())),
+    };
+
+    let members = match thumbprint_members(kty) {
+        Some(members) => members,
+        None => return Err(OAuthError::UnknownError(format!("Unsupported kty {}", kty))),
+    };
+
+    let mut required = BTreeMap::new();
+    for member in members {
+        match jwk.get(*member).and_then(|value| value.as_str()) {
+            Some(value) => { required.insert(*member, value); },
+            None => return Err(OAuthError::UnknownError(format!("JWK has no {}", member))),
+        }
+    }
+
+    // serde_json writes maps without whitespace, BTreeMap keeps the order
+    let json = try!(serde_json::to_string(&required));
+    let digest = try!(hash(MessageDigest::sha256(), json.as_bytes()));
+    Ok(digest.to_base64(base64::URL_SAFE))
+}
+
+// Thumbprint of a key stored on disk
+pub fn thumbprint_file(path: &Path) -> Result<String, OAuthError> {
+    let jwk: Value = try!(read_key_file(path));
+    thumbprint(&jwk)
+}
+
+//--------------------------------------------------------------------------
+// Encoding utilities
+//--------------------------------------------------------------------------
+
+// Utility to convert bytes to base64
+fn to_base64(bytes: &[u8]) -> String {
+    bytes.to_base64(base64::URL_SAFE)
+}
+
+// Utility to convert BigNumRefs to base64
+fn bn_to_base64(bn: &BigNumRef) -> String {
+    to_base64(&bn.to_vec())
+}
+
+// Utility to convert BigNumRefs to fixed size base64, as EC members require
+fn bn_to_padded_base64(bn: &BigNumRef, size: usize) -> Result<String, OAuthError> {
+    let bytes = try!(bn.to_vec_padded(size as i32));
+    Ok(to_base64(&bytes))
+}
+
+fn from_base64(value: &str) -> Result<Vec<u8>, OAuthError> {
+    value.from_base64()
+        .map_err(|err| OAuthError::JWTError(format!("Invalid key component: {}", err)))
+}
+
+// Utility to convert base64 to BigNums
+fn base64_to_bn(value: &str) -> Result<BigNum, OAuthError> {
+    let bytes = try!(from_base64(value));
+    let bn = try!(BigNum::from_slice(&bytes));
+    Ok(bn)
+}
+
+fn check_curve(crv: &str, expected: &str) -> Result<(), OAuthError> {
+    match crv == expected {
+        true => Ok(()),
+        false => Err(OAuthError::UnknownError(format!("Unsupported curve {}", crv))),
+    }
+}
+
+fn p256() -> Result<EcGroup, OAuthError> {
+    let group = try!(EcGroup::from_curve_name(Nid::X9_62_PRIME256V1));
+    Ok(group)
+}
+
+fn p256_point(group: &EcGroup, x: &str, y: &str) -> Result<EcKey<Public>, OAuthError> {
+    let x = try!(base64_to_bn(x));
+    let y = try!(base64_to_bn(y));
+    let key = try!(EcKey::from_public_key_affine_coordinates(group, &x, &y));
+    Ok(key)
+}
+
+//--------------------------------------------------------------------------
+// SecretKey
+//--------------------------------------------------------------------------
+
+impl SecretKey {
+
+    pub fn algorithm(&self) -> Algorithm {
+        match *self {
+            SecretKey::RSA { .. } => Algorithm::RS256,
+            SecretKey::EC { .. } => Algorithm::ES256,
+            SecretKey::OKP { .. } => Algorithm::EdDSA,
+        }
+    }
+
+    pub fn thumbprint(&self) -> Result<String, OAuthError> {
+        thumbprint(&try!(serde_json::to_value(self)))
+    }
+
+    pub fn to_pkey(&self) -> Result<PKey<Private>, OAuthError> {
+        match *self {
+            SecretKey::RSA { ref n, ref e, ref d, ref p, ref q, ref dp, ref dq, ref qi } => {
+                let rsa = try!(Rsa::from_private_components(
+                    try!(base64_to_bn(n)),
+                    try!(base64_to_bn(e)),
+                    try!(base64_to_bn(d)),
+                    try!(base64_to_bn(p)),
+                    try!(base64_to_bn(q)),
+                    try!(base64_to_bn(dp)),
+                    try!(base64_to_bn(dq)),
+                    try!(base64_to_bn(qi))));
+                Ok(try!(PKey::from_rsa(rsa)))
+            },
+
+            SecretKey::EC { ref crv, ref x, ref y, ref d } => {
+                try!(check_curve(crv, "P-256"));
+                let group = try!(p256());
+                let public = try!(p256_point(&group, x, y));
+                let d = try!(base64_to_bn(d));
+                let key = try!(EcKey::from_private_components(&group, &d, public.public_key()));
+                Ok(try!(PKey::from_ec_key(key)))
+            },
+
+            SecretKey::OKP { ref crv, ref d, .. } => {
+                try!(check_curve(crv, "Ed25519"));
+                let d = try!(from_base64(d));
+                Ok(try!(PKey::private_key_from_raw_bytes(&d, Id::ED25519)))
+            },
+        }
+    }
+
+    // Signs the JWS signing input, returning the signature in its JWS form
+    // (raw r || s for ECDSA rather than DER).
+    pub fn sign(&self, input: &[u8]) -> Result<Vec<u8>, OAuthError> {
+        let pkey = try!(self.to_pkey());
+        match *self {
+            SecretKey::RSA { .. } => {
+                let mut signer = try!(Signer::new(MessageDigest::sha256(), &pkey));
+                try!(signer.update(input));
+                Ok(try!(signer.sign_to_vec()))
+            },
+
+            SecretKey::EC { .. } => {
+                let mut signer = try!(Signer::new(MessageDigest::sha256(), &pkey));
+                try!(signer.update(input));
+                let der = try!(signer.sign_to_vec());
+                let signature = try!(EcdsaSig::from_der(&der));
+                let mut raw = try!(signature.r().to_vec_padded(P256_SIZE as i32));
+                raw.extend(try!(signature.s().to_vec_padded(P256_SIZE as i32)));
+                Ok(raw)
+            },
+
+            SecretKey::OKP { .. } => {
+                let mut signer = try!(Signer::new_without_digest(&pkey));
+                Ok(try!(signer.sign_oneshot_to_vec(input)))
+            },
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// PublicKey
+//--------------------------------------------------------------------------
+
+impl PublicKey {
+
+    pub fn kid(&self) -> &str {
+        match *self {
+            PublicKey::RSA { ref kid, .. } => kid,
+            PublicKey::EC { ref kid, .. } => kid,
+            PublicKey::OKP { ref kid, .. } => kid,
+        }
+    }
+
+    pub fn set_kid(&mut self, new_kid: String) {
+        match *self {
+            PublicKey::RSA { ref mut kid, .. } => *kid = new_kid,
+            PublicKey::EC { ref mut kid, .. } => *kid = new_kid,
+            PublicKey::OKP { ref mut kid, .. } => *kid = new_kid,
+        }
+    }
+
+    pub fn kty(&self) -> &'static str {
+        match *self {
+            PublicKey::RSA { .. } => "RSA",
+            PublicKey::EC { .. } => "EC",
+            PublicKey::OKP { .. } => "OKP",
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match *self {
+            PublicKey::RSA { .. } => Algorithm::RS256,
+            PublicKey::EC { .. } => Algorithm::ES256,
+            PublicKey::OKP { .. } => Algorithm::EdDSA,
+        }
+    }
+
+    pub fn thumbprint(&self) -> Result<String, OAuthError> {
+        thumbprint(&try!(serde_json::to_value(self)))
+    }
+
+    pub fn to_pkey(&self) -> Result<PKey<Public>, OAuthError> {
+        match *self {
+            PublicKey::RSA { ref n, ref e, .. } => {
+                let rsa = try!(Rsa::from_public_components(
+                    try!(base64_to_bn(n)),
+                    try!(base64_to_bn(e))));
+                Ok(try!(PKey::from_rsa(rsa)))
+            },
+
+            PublicKey::EC { ref crv, ref x, ref y, .. } => {
+                try!(check_curve(crv, "P-256"));
+                let group = try!(p256());
+                let key = try!(p256_point(&group, x, y));
+                Ok(try!(PKey::from_ec_key(key)))
+            },
+
+            PublicKey::OKP { ref crv, ref x, .. } => {
+                try!(check_curve(crv, "Ed25519"));
+                let x = try!(from_base64(x));
+                Ok(try!(PKey::public_key_from_raw_bytes(&x, Id::ED25519)))
+            },
+        }
+    }
+
+    // Checks a signature in its JWS form against the JWS signing input
+    pub fn verify(&self, input: &[u8], signature: &[u8]) -> Result<bool, OAuthError> {
+        let pkey = try!(self.to_pkey());
+        match *self {
+            PublicKey::RSA { .. } => {
+                let mut verifier = try!(Verifier::new(MessageDigest::sha256(), &pkey));
+                try!(verifier.update(input));
+                Ok(try!(verifier.verify(signature)))
+            },
+
+            PublicKey::EC { .. } => {
+                if signature.len() != 2 * P256_SIZE {
+                    return Ok(false);
+                }
+                let r = try!(BigNum::from_slice(&signature[..P256_SIZE]));
+                let s = try!(BigNum::from_slice(&signature[P256_SIZE..]));
+                let der = try!(try!(EcdsaSig::from_private_components(r, s)).to_der());
+                let mut verifier = try!(Verifier::new(MessageDigest::sha256(), &pkey));
+                try!(verifier.update(input));
+                Ok(try!(verifier.verify(&der)))
+            },
+
+            PublicKey::OKP { .. } => {
+                let mut verifier = try!(Verifier::new_without_digest(&pkey));
+                Ok(try!(verifier.verify_oneshot(signature, input)))
+            },
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Generation
+//--------------------------------------------------------------------------
+
+pub fn generate_keypair() -> Result<(SecretKey, PublicKey), OAuthError> {
+    generate(KeySpec::default())
+}
+
+// Generates a key pair, its kid being the thumbprint of the public key
+pub fn generate(spec: KeySpec) -> Result<(SecretKey, PublicKey), OAuthError> {
+    let (secret_key, mut public_key) = match spec {
+        KeySpec::RSA(bits) => try!(generate_rsa(bits)),
+        KeySpec::P256 => try!(generate_p256()),
+        KeySpec::Ed25519 => try!(generate_ed25519()),
+    };
+    let kid = try!(public_key.thumbprint());
+    public_key.set_kid(kid);
+    Ok((secret_key, public_key))
+}
+
+fn missing(component: &str) -> OAuthError {
+    OAuthError::UnknownError(format!("Generated RSA key has no {}", component))
+}
+
+fn generate_rsa(bits: u32) -> Result<(SecretKey, PublicKey), OAuthError> {
+    let rsa = try!(Rsa::generate(bits));
+
+    let secret_key = SecretKey::RSA {
+        n: bn_to_base64(rsa.n()),
+        e: bn_to_base64(rsa.e()),
+        d: bn_to_base64(rsa.d()),
+        p: bn_to_base64(try!(rsa.p().ok_or(missing("p")))),
+        q: bn_to_base64(try!(rsa.q().ok_or(missing("q")))),
+        dp: bn_to_base64(try!(rsa.dmp1().ok_or(missing("dp")))),
+        dq: bn_to_base64(try!(rsa.dmq1().ok_or(missing("dq")))),
+        qi: bn_to_base64(try!(rsa.iqmp().ok_or(missing("qi")))),
+    };
+
+    let public_key = PublicKey::RSA {
+        kid: String::new(),
+        n: bn_to_base64(rsa.n()),
+        e: bn_to_base64(rsa.e()),
+    };
+
+    Ok((secret_key, public_key))
+}
+
+fn generate_p256() -> Result<(SecretKey, PublicKey), OAuthError> {
+    let group = try!(p256());
+    let key = try!(EcKey::generate(&group));
+
+    let mut ctx = try!(BigNumContext::new());
+    let mut x = try!(BigNum::new());
+    let mut y = try!(BigNum::new());
+    let point: &EcPoint = key.public_key();
+    try!(point.affine_coordinates_gfp(&group, &mut x, &mut y, &mut ctx));
+
+    let x = try!(bn_to_padded_base64(&x, P256_SIZE));
+    let y = try!(bn_to_padded_base64(&y, P256_SIZE));
+
+    let secret_key = SecretKey::EC {
+        crv: "P-256".to_string(),
+        x: x.clone(),
+        y: y.clone(),
+        d: try!(bn_to_padded_base64(key.private_key(), P256_SIZE)),
+    };
+
+    let public_key = PublicKey::EC {
+        kid: String::new(),
+        crv: "P-256".to_string(),
+        x: x,
+        y: y,
+    };
+
+    Ok((secret_key, public_key))
+}
+
+fn generate_ed25519() -> Result<(SecretKey, PublicKey), OAuthError> {
+    let key = try!(PKey::generate_ed25519());
+    let x = to_base64(&try!(key.raw_public_key()));
+
+    let secret_key = SecretKey::OKP {
+        crv: "Ed25519".to_string(),
+        x: x.clone(),
+        d: to_base64(&try!(key.raw_private_key())),
+    };
+
+    let public_key = PublicKey::OKP {
+        kid: String::new(),
+        crv: "Ed25519".to_string(),
+        x: x,
+    };
+
+    Ok((secret_key, public_key))
+}
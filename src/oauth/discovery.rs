@@ -0,0 +1,162 @@
+use reqwest::StatusCode;
+use serde_json;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use error::OAuthError;
+use super::client::ProviderClient;
+
+// A cached discovery document younger than this is used without fetching
+pub const DEFAULT_CACHE_TTL: u64 = 86400;
+
+//--------------------------------------------------------------------------
+// ProviderMetadata
+//--------------------------------------------------------------------------
+
+// The parts of /.well-known/openid-configuration we use (OpenID Connect
+// Discovery 1.0 section 3, plus RFC 7009's revocation_endpoint).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    #[serde(default)]
+    pub userinfo_endpoint: Option<String>,
+    #[serde(default)]
+    pub revocation_endpoint: Option<String>,
+    pub jwks_uri: String,
+}
+
+//--------------------------------------------------------------------------
+// Endpoints
+//--------------------------------------------------------------------------
+
+// Where the provider's endpoints live, either discovered or built from
+// OAUTH_URI / PROFILE_URI.
+#[derive(Clone, Debug)]
+pub struct Endpoints {
+    pub issuer: String,
+    pub authorization: String,
+    pub token: String,
+    pub userinfo: String,
+    pub revocation: Option<String>,
+    pub jwks: String,
+}
+
+fn join(base: &str, path: &str) -> String {
+    [base.trim_right_matches('/'), path].join("/")
+}
+
+impl Endpoints {
+
+    // FxA's layout, used when discovery is off
+    pub fn from_uris(oauth_uri: &str, profile_uri: &str, issuer: &str) -> Endpoints {
+        Endpoints {
+            issuer: issuer.to_string(),
+            authorization: join(oauth_uri, "authorization"),
+            token: join(oauth_uri, "token"),
+            userinfo: join(profile_uri, "profile"),
+            revocation: Some(join(oauth_uri, "destroy")),
+            jwks: join(oauth_uri, "jwks"),
+        }
+    }
+
+    pub fn from_metadata(metadata: &ProviderMetadata, profile_uri: &str) -> Endpoints {
+        Endpoints {
+            issuer: metadata.issuer.clone(),
+            authorization: metadata.authorization_endpoint.clone(),
+            token: metadata.token_endpoint.clone(),
+            userinfo: metadata.userinfo_endpoint.clone()
+                .unwrap_or(join(profile_uri, "profile")),
+            revocation: metadata.revocation_endpoint.clone(),
+            jwks: metadata.jwks_uri.clone(),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Discovery
+//--------------------------------------------------------------------------
+
+pub fn configuration_uri(issuer: &str) -> String {
+    join(issuer, ".well-known/openid-configuration")
+}
+
+pub fn fetch(client: &ProviderClient, issuer: &str) -> Result<ProviderMetadata, OAuthError> {
+    let url = configuration_uri(issuer);
+    let mut rsp = try!(client.get(&url));
+
+    if *rsp.status() != StatusCode::Ok {
+        return Err(OAuthError::UnknownError(format!("Discovery request to {} failed with {}", url, rsp.status())));
+    }
+
+    let metadata: ProviderMetadata = try!(rsp.json());
+
+    // The document must describe the issuer we asked about (section 4.3)
+    if metadata.issuer.trim_right_matches('/') != issuer.trim_right_matches('/') {
+        return Err(OAuthError::UnknownError(format!("Discovery document is for issuer {}", metadata.issuer)));
+    }
+
+    Ok(metadata)
+}
+
+fn cache_age(path: &Path) -> Option<Duration> {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+}
+
+fn read_cache(path: &Path) -> Result<ProviderMetadata, OAuthError> {
+    let mut file = try!(File::open(path));
+    let mut contents = String::new();
+    let _ = try!(file.read_to_string(&mut contents));
+    let metadata: ProviderMetadata = try!(serde_json::from_str(&contents));
+    Ok(metadata)
+}
+
+fn write_cache(path: &Path, metadata: &ProviderMetadata) -> Result<(), OAuthError> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = try!(File::create(&tmp_path));
+        try!(file.write_all(try!(serde_json::to_string(metadata)).as_bytes()));
+    }
+    try!(fs::rename(&tmp_path, path));
+    Ok(())
+}
+
+// Uses the cached document while it is fresh, otherwise fetches a new one.
+// A stale cache is still better than no provider at all, so it is used when
+// the provider cannot be reached.
+pub fn discover(client: &ProviderClient, issuer: &str, cache_path: Option<&Path>, cache_ttl: u64) -> Result<ProviderMetadata, OAuthError> {
+    let cache_path = match cache_path {
+        Some(path) => path,
+        None => return fetch(client, issuer),
+    };
+
+    let fresh = cache_age(cache_path)
+        .map(|age| age < Duration::from_secs(cache_ttl))
+        .unwrap_or(false);
+    if fresh {
+        if let Ok(metadata) = read_cache(cache_path) {
+            if metadata.issuer.trim_right_matches('/') == issuer.trim_right_matches('/') {
+                return Ok(metadata);
+            }
+        }
+    }
+
+    match fetch(client, issuer) {
+        Ok(metadata) => {
+            try!(write_cache(cache_path, &metadata));
+            Ok(metadata)
+        },
+        Err(err) => match read_cache(cache_path) {
+            Ok(ref metadata) if metadata.issuer.trim_right_matches('/') == issuer.trim_right_matches('/') => {
+                println!("Discovery failed ({}), using cached {:?}", err, cache_path);
+                Ok(metadata.clone())
+            },
+            _ => Err(err),
+        },
+    }
+}
@@ -1,10 +1,204 @@
 use rustc_serialize::base64::{self, ToBase64};
 use std::collections::HashMap;
 use rand::{self, Rng};
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use super::OAuthRequest;
+use super::settings::{Settings, FlowStoreKind};
+use super::store::JsonFile;
+use error::{OAuthError, ProviderError};
 
-pub struct Flow(pub Arc<Mutex<HashMap<String, bool>>>);
+// Default lifetime of a pending login (10 minutes)
+pub const DEFAULT_TTL: u64 = 600;
+
+//--------------------------------------------------------------------------
+// FlowState
+//--------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FlowState {
+    // Milliseconds since the unix epoch
+    pub created_at: u64,
+    // PKCE secret sent with the token request
+    #[serde(default)]
+    pub code_verifier: Option<String>,
+    // signin, signup or force_auth
+    #[serde(default)]
+    pub action: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    // Allow-listed local path to redirect to once logged in
+    #[serde(default)]
+    pub return_to: Option<String>,
+    // Origin header of the page which started the login
+    #[serde(default)]
+    pub origin: Option<String>,
+    // OpenID Connect nonce the id_token must echo
+    #[serde(default)]
+    pub nonce: Option<String>,
+}
+
+// Milliseconds since the unix epoch
+pub fn now() -> u64 {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    to_millis(&elapsed)
+}
+
+fn to_millis(duration: &Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+impl FlowState {
+
+    pub fn new() -> FlowState {
+        FlowState {
+            created_at: now(),
+            code_verifier: None,
+            action: None,
+            scopes: Vec::new(),
+            return_to: None,
+            origin: None,
+            nonce: None,
+        }
+    }
+
+    #[inline]
+    pub fn set_action(mut self, action: String) -> FlowState {
+        self.action = Some(action);
+        self
+    }
+
+    #[inline]
+    pub fn set_scopes(mut self, scopes: Vec<String>) -> FlowState {
+        self.scopes = scopes;
+        self
+    }
+
+    #[inline]
+    pub fn set_return_to(mut self, return_to: Option<String>) -> FlowState {
+        self.return_to = return_to;
+        self
+    }
+
+    #[inline]
+    pub fn set_origin(mut self, origin: Option<String>) -> FlowState {
+        self.origin = origin;
+        self
+    }
+
+    #[inline]
+    pub fn set_nonce(mut self, nonce: String) -> FlowState {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    // Where to send the user once the login completes
+    pub fn redirect_path(&self) -> String {
+        self.return_to.clone().unwrap_or("/".to_string())
+    }
+
+    #[inline]
+    pub fn set_code_verifier(mut self, code_verifier: String) -> FlowState {
+        self.code_verifier = Some(code_verifier);
+        self
+    }
+
+    #[inline]
+    pub fn is_expired(&self, ttl: &Duration) -> bool {
+        now() >= self.created_at + to_millis(ttl)
+    }
+}
+
+// Evicts every expired state, returning how many were removed
+fn sweep_expired(data: &mut HashMap<String, FlowState>, ttl: &Duration) -> usize {
+    let before = data.len();
+    data.retain(|_, state| !state.is_expired(ttl));
+    before - data.len()
+}
+
+//--------------------------------------------------------------------------
+// FlowStore
+//--------------------------------------------------------------------------
+
+pub trait FlowStore: Send + Sync {
+    fn insert(&self, nonce: String, state: FlowState) -> Result<(), OAuthError>;
+
+    fn find(&self, nonce: &str) -> Result<Option<FlowState>, OAuthError>;
+
+    // Removes and returns the state, so that it can be used only once
+    fn take(&self, nonce: &str) -> Result<Option<FlowState>, OAuthError>;
+
+    // Evicts expired states, returning how many were removed
+    fn sweep(&self, ttl: &Duration) -> Result<usize, OAuthError>;
+}
+
+pub struct MemoryFlowStore(Mutex<HashMap<String, FlowState>>);
+
+impl MemoryFlowStore {
+    pub fn new() -> MemoryFlowStore {
+        MemoryFlowStore(Mutex::new(HashMap::new()))
+    }
+}
+
+impl FlowStore for MemoryFlowStore {
+    fn insert(&self, nonce: String, state: FlowState) -> Result<(), OAuthError> {
+        let mut data = self.0.lock().unwrap();
+        data.insert(nonce, state);
+        Ok(())
+    }
+
+    fn find(&self, nonce: &str) -> Result<Option<FlowState>, OAuthError> {
+        let data = self.0.lock().unwrap();
+        Ok(data.get(nonce).cloned())
+    }
+
+    fn take(&self, nonce: &str) -> Result<Option<FlowState>, OAuthError> {
+        let mut data = self.0.lock().unwrap();
+        Ok(data.remove(nonce))
+    }
+
+    fn sweep(&self, ttl: &Duration) -> Result<usize, OAuthError> {
+        let mut data = self.0.lock().unwrap();
+        Ok(sweep_expired(&mut data, ttl))
+    }
+}
+
+// Persists pending logins to a JSON file so that they survive restarts and
+// can be shared by several instances pointing at the same path.
+pub struct FileFlowStore(JsonFile<FlowState>);
+
+impl FileFlowStore {
+    pub fn new(path: &str) -> FileFlowStore {
+        FileFlowStore(JsonFile::new(path))
+    }
+}
+
+impl FlowStore for FileFlowStore {
+    fn insert(&self, nonce: String, state: FlowState) -> Result<(), OAuthError> {
+        self.0.update(|data| { data.insert(nonce, state); })
+    }
+
+    fn find(&self, nonce: &str) -> Result<Option<FlowState>, OAuthError> {
+        self.0.read(|data| data.get(nonce).cloned())
+    }
+
+    fn take(&self, nonce: &str) -> Result<Option<FlowState>, OAuthError> {
+        self.0.update(|data| data.remove(nonce))
+    }
+
+    fn sweep(&self, ttl: &Duration) -> Result<usize, OAuthError> {
+        self.0.update(|data| sweep_expired(data, ttl))
+    }
+}
+
+//--------------------------------------------------------------------------
+// Flow
+//--------------------------------------------------------------------------
+
+pub struct Flow {
+    store: Box<FlowStore>,
+    ttl: Duration,
+}
 
 // Generate a random string (32-bytes)
 pub fn random_string() -> String {
@@ -18,44 +212,121 @@ pub fn random_string() -> String {
 impl Flow {
 
     pub fn new() -> Flow {
-        let flow = Arc::new(Mutex::new(HashMap::<String, bool>::new()));
-        Flow(flow)
+        Flow::with_ttl(Duration::from_secs(DEFAULT_TTL))
     }
 
-    pub fn find(&self, nonce: String) -> bool {
-        let data = self.0.lock().unwrap();
-        match data.get(&nonce) {
-            Some(_) => true,
-            None => false,
+    pub fn with_ttl(ttl: Duration) -> Flow {
+        Flow::with_store(Box::new(MemoryFlowStore::new()), ttl)
+    }
+
+    pub fn with_store(store: Box<FlowStore>, ttl: Duration) -> Flow {
+        Flow {
+            store: store,
+            ttl: ttl,
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Flow {
+        let ttl = Duration::from_secs(settings.flow_ttl);
+        match settings.flow_store {
+            FlowStoreKind::Memory => Flow::with_ttl(ttl),
+            FlowStoreKind::File(ref path) => {
+                Flow::with_store(Box::new(FileFlowStore::new(path)), ttl)
+            },
         }
     }
 
-    // Generates a unique persistent nonce
-    pub fn generate_persistent_nonce(&self) -> String {
+    #[inline]
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn find(&self, nonce: String) -> Result<bool, OAuthError> {
+        let state = try!(self.store.find(&nonce));
+        match state {
+            Some(state) => Ok(!state.is_expired(&self.ttl)),
+            None => Ok(false),
+        }
+    }
+
+    // Generates a unique persistent nonce, evicting abandoned logins
+    pub fn generate_persistent_nonce(&self) -> Result<String, OAuthError> {
+        self.start(FlowState::new())
+    }
+
+    // Stores the state under a new nonce, evicting abandoned logins
+    pub fn start(&self, state: FlowState) -> Result<String, OAuthError> {
         let r = random_string();
-        let mut data = self.0.lock().unwrap();
-        let _ = data.insert(r.clone(), true).unwrap();
-        r
+        try!(self.store.sweep(&self.ttl));
+        try!(self.store.insert(r.clone(), state));
+        Ok(r)
     }
 
     // Deletes nonce from shared state
-    pub fn remove(&self, nonce: String) -> bool {
-        let mut data = self.0.lock().unwrap();
-        data.remove(&nonce).unwrap()
-    }
-
-    // Verifies nonce against self / client / session state
-    pub fn verify(&self, req: OAuthRequest, nonce: String) -> bool {
-        match req.code {
-            Some(_) => {
-                let data = self.0.lock().unwrap();
-                match data.get(&nonce) {
-                    Some(_) => req.state == nonce,
-                    None => false,
-                }
+    pub fn remove(&self, nonce: String) -> Result<bool, OAuthError> {
+        let state = try!(self.store.take(&nonce));
+        Ok(state.is_some())
+    }
+
+    // Evicts expired nonces from shared state
+    pub fn sweep(&self) -> Result<usize, OAuthError> {
+        self.store.sweep(&self.ttl)
+    }
+
+    // Checks a callback's state against self / client / session state.
+    // The state is consumed whatever the outcome so that it can never be
+    // replayed.
+    pub fn check(&self, state: &str, nonce: &str) -> Result<FlowCheck, OAuthError> {
+        match try!(self.store.take(state)) {
+            Some(ref pending) if pending.is_expired(&self.ttl) => Ok(FlowCheck::Unknown),
+            Some(pending) => match state == nonce {
+                true => Ok(FlowCheck::Valid(pending)),
+                false => Ok(FlowCheck::DifferentBrowser(pending)),
             },
+            None => Ok(FlowCheck::Unknown),
+        }
+    }
+
+    // Verifies nonce against self / client / session state, returning the
+    // stored state when valid.
+    pub fn verify(&self, req: OAuthRequest, nonce: String) -> Result<Option<FlowState>, OAuthError> {
+        match (try!(self.check(&req.state, &nonce)), req.code) {
+            (FlowCheck::Valid(state), Some(_)) => Ok(Some(state)),
+            _ => Ok(None),
+        }
+    }
+}
 
-            None => false,
+// What the state of a callback turned out to be
+#[derive(Debug)]
+pub enum FlowCheck {
+    // Started in this browser and still pending
+    Valid(FlowState),
+    // Pending, but the browser has no matching state cookie: the login was
+    // started in one browser and finished in another
+    DifferentBrowser(FlowState),
+    // Never issued, already used or abandoned for too long
+    Unknown,
+}
+
+impl FlowCheck {
+
+    // The pending login req finishes. Only a login started in this browser
+    // can fail at the provider, anybody can craft an error callback, which
+    // is also why error_uri is ignored.
+    pub fn finish(self, req: &OAuthRequest) -> Result<FlowState, OAuthError> {
+        let state = match self {
+            FlowCheck::Valid(state) => state,
+            FlowCheck::DifferentBrowser(_) => {
+                return Err(OAuthError::DifferentBrowserError("State cookie does not match the callback".to_string()));
+            },
+            FlowCheck::Unknown => return Err(OAuthError::LoginExpired),
+        };
+
+        if let Some(ref error) = req.error {
+            return Err(OAuthError::ProviderError(ProviderError::from_code(error), req.error_description.clone()));
         }
+
+        Ok(state)
     }
 }
@@ -0,0 +1,137 @@
+use rocket::Outcome;
+use rocket::http::Status;
+use rocket::request::{self, Request, FromRequest};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use super::session::Session;
+use error::OAuthError;
+
+//--------------------------------------------------------------------------
+// Scopes
+//--------------------------------------------------------------------------
+
+// A scope a route can require with RequireScope<S>
+pub trait Scope {
+    fn name() -> &'static str;
+}
+
+// Everything in the profile
+pub struct Profile;
+
+impl Scope for Profile {
+    fn name() -> &'static str { "profile" }
+}
+
+// Only the email address
+pub struct Email;
+
+impl Scope for Email {
+    fn name() -> &'static str { "profile:email" }
+}
+
+// A granted scope covers itself and the scopes below it, "profile" covers
+// "profile:email" but not the other way round.
+pub fn scope_covers(granted: &str, required: &str) -> bool {
+    granted == required ||
+        (required.starts_with(granted) && required[granted.len()..].starts_with(':'))
+}
+
+//--------------------------------------------------------------------------
+// AuthenticatedUser
+//--------------------------------------------------------------------------
+
+// The user of the current session. Fails with 401 when nobody is logged in,
+// use Option<AuthenticatedUser> for routes which also serve anonymous users.
+pub struct AuthenticatedUser {
+    pub uid: String,
+    pub email: String,
+    pub scopes: Vec<String>,
+    pub session: Session,
+}
+
+impl AuthenticatedUser {
+
+    pub fn from_session(session: Session) -> AuthenticatedUser {
+        AuthenticatedUser {
+            uid: session.data.uid.clone(),
+            email: session.data.email.clone(),
+            scopes: session.data.scopes.clone(),
+            session: session,
+        }
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| scope_covers(granted, scope))
+    }
+
+    pub fn require_scope(&self, scope: &str) -> Result<(), OAuthError> {
+        match self.has_scope(scope) {
+            true => Ok(()),
+            false => Err(OAuthError::MissingScope(scope.to_string())),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AuthenticatedUser {
+    type Error = OAuthError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AuthenticatedUser, OAuthError> {
+        match Session::from_request(request) {
+            Outcome::Success(session) => Outcome::Success(AuthenticatedUser::from_session(session)),
+            Outcome::Failure((status, _)) if status == Status::Unauthorized => {
+                Outcome::Failure((status, OAuthError::LoginRequired))
+            },
+            Outcome::Failure((status, _)) => Outcome::Failure((status, OAuthError::HTTPError(status))),
+            Outcome::Forward(_) => Outcome::Forward(()),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// RequireScope
+//--------------------------------------------------------------------------
+
+// The user of the current session, who must have been granted S. Fails
+// with 401 when nobody is logged in and 403 when S is missing. Catchers
+// only get the status, so routes which should name the missing scope take
+// Result<RequireScope<S>, OAuthError> and answer with the error.
+pub struct RequireScope<S: Scope> {
+    pub user: AuthenticatedUser,
+    scope: PhantomData<S>,
+}
+
+impl<S: Scope> RequireScope<S> {
+
+    pub fn new(user: AuthenticatedUser) -> Result<RequireScope<S>, OAuthError> {
+        try!(user.require_scope(S::name()));
+        Ok(RequireScope {
+            user: user,
+            scope: PhantomData,
+        })
+    }
+}
+
+impl<S: Scope> Deref for RequireScope<S> {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &AuthenticatedUser {
+        &self.user
+    }
+}
+
+impl<'a, 'r, S: Scope> FromRequest<'a, 'r> for RequireScope<S> {
+    type Error = OAuthError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<RequireScope<S>, OAuthError> {
+        let user = match AuthenticatedUser::from_request(request) {
+            Outcome::Success(user) => user,
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(_) => return Outcome::Forward(()),
+        };
+
+        match RequireScope::new(user) {
+            Ok(required) => Outcome::Success(required),
+            Err(err) => Outcome::Failure((err.status(), err)),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use serde_json::Value;
+use error::OAuthError;
+use super::settings::Settings;
+use super::crypto::jwks::RemoteKeySet;
+use super::client::ProviderClient;
+use super::crypto::jwt::{self, LEEWAY};
+
+//--------------------------------------------------------------------------
+// IdTokenClaims
+//--------------------------------------------------------------------------
+
+// The OpenID Connect claims we rely on, exp and iat are required
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    // A single audience or an array of them
+    pub aud: Value,
+    pub exp: u64,
+    pub iat: u64,
+    #[serde(default)]
+    pub nonce: Option<String>,
+}
+
+impl IdTokenClaims {
+
+    pub fn has_audience(&self, audience: &str) -> bool {
+        match self.aud {
+            Value::String(ref aud) => aud == audience,
+            Value::Array(ref auds) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// IdTokenVerifier
+//--------------------------------------------------------------------------
+
+// Verifies id_tokens returned by the token endpoint against the provider's
+// published keys.
+pub struct IdTokenVerifier {
+    keys: RemoteKeySet,
+    issuer: String,
+    client_id: String,
+}
+
+impl IdTokenVerifier {
+
+    pub fn new(jwks_uri: String, issuer: String, client_id: String) -> IdTokenVerifier {
+        IdTokenVerifier {
+            keys: RemoteKeySet::new(jwks_uri),
+            issuer: issuer,
+            client_id: client_id,
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> IdTokenVerifier {
+        IdTokenVerifier::new(settings.endpoints.jwks.clone(),
+                             settings.endpoints.issuer.clone(),
+                             settings.client_id.clone())
+    }
+
+    // Checks the signature with the key named by the token's kid, then the
+    // claims. `nonce` is the one stored with the login's Flow state.
+    pub fn verify(&self, client: &ProviderClient, token: &str, nonce: Option<&str>) -> Result<IdTokenClaims, OAuthError> {
+        let header = try!(jwt::decode_header(token));
+        let kid = match header.kid {
+            Some(kid) => kid,
+            None => return Err(OAuthError::JWTError("id_token has no kid".to_string())),
+        };
+
+        let key = try!(self.keys.find(client, &kid));
+        let claims: IdTokenClaims = try!(jwt::decode(token, &key));
+        try!(self.check_claims(&claims, nonce));
+        Ok(claims)
+    }
+
+    // jwt::decode already rejected an expired token
+    pub fn check_claims(&self, claims: &IdTokenClaims, nonce: Option<&str>) -> Result<(), OAuthError> {
+        if claims.iss != self.issuer {
+            return Err(OAuthError::JWTError(format!("Unexpected iss {}", claims.iss)));
+        }
+
+        if !claims.has_audience(&self.client_id) {
+            return Err(OAuthError::JWTError("id_token was not issued to this client".to_string()));
+        }
+
+        if claims.iat > jwt::timestamp() + LEEWAY {
+            return Err(OAuthError::JWTError("id_token issued in the future".to_string()));
+        }
+
+        if let Some(nonce) = nonce {
+            if claims.nonce.as_ref().map(|claim| claim.as_str()) != Some(nonce) {
+                return Err(OAuthError::JWTError("id_token nonce does not match".to_string()));
+            }
+        }
+
+        Ok(())
+    }
+}
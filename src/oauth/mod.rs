@@ -1,44 +1,72 @@
 pub mod settings;
 pub mod flow;
 pub mod crypto;
+pub mod store;
+pub mod pkce;
+pub mod id_token;
+pub mod discovery;
+pub mod secret;
+pub mod reload;
+pub mod cookie;
+pub mod session;
+pub mod guard;
+pub mod client;
 
-use rocket::http::{CookieJar};
 use self::settings::Settings;
-use self::flow::Flow;
+use self::secret::Secret;
+use self::flow::{Flow, FlowState};
+use error::OAuthError;
+use rocket::http::Status;
+
+// Scope OpenID providers issue refresh tokens for
+pub const OFFLINE_ACCESS_SCOPE: &'static str = "offline_access";
 
 //--------------------------------------------------------------------------
 // API
 //--------------------------------------------------------------------------
 
-pub fn build_signin_response<'a>(settings: &'a Settings, flow: &'a Flow) -> OAuthResponse {
-    OAuthResponse::new(settings, flow)
-        .set_action("signin".to_string())
+pub fn build_signin_response<'a>(settings: &'a Settings, flow: &'a Flow, params: FlowParams) -> Result<OAuthResponse, OAuthError> {
+    OAuthResponse::new(settings, flow, params, Some("signin".to_string()))
+}
+
+pub fn build_signup_response<'a>(settings: &'a Settings, flow: &'a Flow, params: FlowParams) -> Result<OAuthResponse, OAuthError> {
+    OAuthResponse::new(settings, flow, params, Some("signup".to_string()))
 }
 
-pub fn build_signup_response<'a>(settings: &'a Settings, flow: &'a Flow) -> OAuthResponse {
-    OAuthResponse::new(settings, flow)
-        .set_action("signup".to_string())
+pub fn build_best_choice_response<'a>(settings: &'a Settings, flow: &'a Flow, params: FlowParams) -> Result<OAuthResponse, OAuthError> {
+    OAuthResponse::new(settings, flow, params, None)
 }
 
-pub fn build_best_choice_response<'a>(settings: &'a Settings, flow: &'a Flow) -> OAuthResponse {
-    OAuthResponse::new(settings, flow)
+pub fn build_force_auth_response<'a>(settings: &'a Settings, flow: &'a Flow, params: FlowParams, email: String) -> Result<OAuthResponse, OAuthError> {
+    let response = try!(OAuthResponse::new(settings, flow, params, Some("force_auth".to_string())));
+    Ok(response.set_email(email.clone()))
 }
 
-pub fn build_force_auth_response<'a>(settings: &'a Settings, flow: &'a Flow, email: String) -> OAuthResponse {
-    OAuthResponse::new(settings, flow)
-        .set_action("force_auth".to_string())
-        .set_email(email.clone())
+//--------------------------------------------------------------------------
+// FlowParams
+//--------------------------------------------------------------------------
+
+// What the client asked for when starting a login
+#[derive(Clone, Default)]
+pub struct FlowParams {
+    pub return_to: Option<String>,
+    pub scopes: Vec<String>,
+    pub origin: Option<String>,
 }
 
 //--------------------------------------------------------------------------
 // OAuthRequest
 //--------------------------------------------------------------------------
 
+// The provider's redirect back: a code on success, an RFC 6749 error and
+// its description otherwise
 #[derive(Deserialize, FromForm, Clone)]
 pub struct OAuthRequest {
     pub state: String,
     pub code: Option<String>,
     pub error: Option<String>,
+    pub error_description: Option<String>,
+    pub error_uri: Option<String>,
 }
 
 //--------------------------------------------------------------------------
@@ -55,23 +83,70 @@ pub struct OAuthResponse {
     pub redirect_uri: String,
     pub oauth_uri: String,
     pub content_uri: String,
+    pub authorization_endpoint: String,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    // Echoed back in the id_token
+    pub nonce: Option<String>,
+    // "offline" asks FxA for a refresh token
+    pub access_type: Option<String>,
 }
 
 impl OAuthResponse {
 
-    pub fn new(settings: &Settings, flow: &Flow) -> OAuthResponse {
-        //let mut flow = flow.0.get_mut().unwrap();
-        let nonce = flow.generate_persistent_nonce();
-        OAuthResponse {
+    pub fn new(settings: &Settings, flow: &Flow, params: FlowParams, action: Option<String>) -> Result<OAuthResponse, OAuthError> {
+        if let Some(ref return_to) = params.return_to {
+            if !settings.allows_return_to(return_to) {
+                return Err(OAuthError::HTTPError(Status::BadRequest));
+            }
+        }
+
+        // OpenID providers hand out refresh tokens for the offline_access
+        // scope, FxA for access_type=offline.
+        let mut scopes = params.scopes;
+        let access_type = match settings.offline_access {
+            true => {
+                if settings.discovery_issuer.is_some() && !scopes.iter().any(|scope| scope == OFFLINE_ACCESS_SCOPE) {
+                    scopes.push(OFFLINE_ACCESS_SCOPE.to_string());
+                }
+                Some("offline".to_string())
+            },
+            false => None,
+        };
+
+        let scope = match scopes.is_empty() {
+            true => None,
+            false => Some(scopes.clone()),
+        };
+
+        let pkce = try!(pkce::generate());
+        let id_token_nonce = flow::random_string();
+        let mut state = FlowState::new()
+            .set_code_verifier(pkce.code_verifier)
+            .set_nonce(id_token_nonce.clone())
+            .set_scopes(scopes)
+            .set_return_to(params.return_to)
+            .set_origin(params.origin);
+        if let Some(ref action) = action {
+            state = state.set_action(action.clone());
+        }
+
+        let nonce = try!(flow.start(state));
+        Ok(OAuthResponse {
             state: nonce,
-            action: None,
+            action: action,
             client_id: settings.client_id.clone(),
             email: None,
-            scope: None,
+            scope: scope,
             redirect_uri: settings.redirect_uri.clone(),
             oauth_uri: settings.oauth_uri.clone(),
             content_uri: settings.content_uri.clone(),
-        }
+            authorization_endpoint: settings.endpoints.authorization.clone(),
+            code_challenge: Some(pkce.code_challenge),
+            code_challenge_method: Some(pkce::CHALLENGE_METHOD.to_string()),
+            nonce: Some(id_token_nonce),
+            access_type: access_type,
+        })
     }
 
     #[inline]
@@ -117,20 +192,32 @@ impl OAuthResponse {
 // TokenRequest
 //--------------------------------------------------------------------------
 
-#[derive(Serialize, Deserialize)]
-pub struct TokenRequest {
+// Sent to the token endpoint, as a form or JSON like RevocationRequest
+#[derive(Serialize)]
+pub struct TokenRequest<'a> {
+    pub grant_type: String,
     pub code: String,
+    pub redirect_uri: String,
     pub client_id: String,
-    pub client_secret: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<&'a Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_verifier: Option<String>,
 }
 
-impl TokenRequest {
-    pub fn new(settings: &Settings, session: &CookieJar) -> TokenRequest {
-        let cookie = session.find("code").unwrap();
+impl<'a> TokenRequest<'a> {
+    pub fn new(settings: &'a Settings, code: String, code_verifier: Option<String>) -> TokenRequest<'a> {
+        let client_secret = match settings.public_client {
+            true => None,
+            false => Some(&settings.client_secret),
+        };
         TokenRequest {
-            code: cookie.value().to_string(),
+            grant_type: "authorization_code".to_string(),
+            code: code,
+            redirect_uri: settings.redirect_uri.clone(),
             client_id: settings.client_id.clone(),
-            client_secret: settings.client_secret.clone(),
+            client_secret: client_secret,
+            code_verifier: code_verifier,
         }
     }
 }
@@ -141,19 +228,104 @@ impl TokenRequest {
 
 #[derive(Serialize, Deserialize)]
 pub struct TokenResponse {
+    // Refresh responses may leave the scopes out
+    #[serde(default)]
     pub scopes: Vec<String>,
     pub token_type: String,
     pub access_token: String,
+    // Only present when the openid scope was granted
+    #[serde(default)]
+    pub id_token: Option<String>,
+    // Only present for offline access, and not always renewed by a refresh
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    // Seconds the access token is valid for
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+//--------------------------------------------------------------------------
+// RefreshRequest
+//--------------------------------------------------------------------------
+
+// Sent to the token endpoint like TokenRequest
+#[derive(Serialize)]
+pub struct RefreshRequest<'a> {
+    pub grant_type: String,
+    pub refresh_token: String,
+    pub client_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<&'a Secret>,
+}
+
+impl<'a> RefreshRequest<'a> {
+    pub fn new(settings: &'a Settings, refresh_token: String) -> RefreshRequest<'a> {
+        let client_secret = match settings.public_client {
+            true => None,
+            false => Some(&settings.client_secret),
+        };
+        RefreshRequest {
+            grant_type: "refresh_token".to_string(),
+            refresh_token: refresh_token,
+            client_id: settings.client_id.clone(),
+            client_secret: client_secret,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// RevocationRequest
+//--------------------------------------------------------------------------
+
+// FxA's /destroy takes these fields as JSON, RFC 7009 endpoints as a form
+#[derive(Serialize)]
+pub struct RevocationRequest<'a> {
+    pub token: String,
+    pub token_type_hint: String,
+    pub client_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<&'a Secret>,
+}
+
+impl<'a> RevocationRequest<'a> {
+    pub fn new(settings: &'a Settings, token: String, token_type_hint: &str) -> RevocationRequest<'a> {
+        let client_secret = match settings.public_client {
+            true => None,
+            false => Some(&settings.client_secret),
+        };
+        RevocationRequest {
+            token: token,
+            token_type_hint: token_type_hint.to_string(),
+            client_id: settings.client_id.clone(),
+            client_secret: client_secret,
+        }
+    }
 }
 
 //--------------------------------------------------------------------------
 // Access Response
 //--------------------------------------------------------------------------
 
-#[derive(Serialize, Deserialize)]
+// The user's profile, as the provider's profile endpoint returns it. Only
+// uid and email are always there, the rest depends on the granted scopes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AccessResponse {
     pub uid: String,
     pub email: String,
+    #[serde(rename = "displayName", default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub avatar: Option<String>,
+    // Whether avatar is the generated placeholder
+    #[serde(rename = "avatarDefault", default)]
+    pub avatar_default: Option<bool>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    // How the user authenticated, e.g. ["pwd", "otp"]
+    #[serde(rename = "amrValues", default)]
+    pub amr_values: Vec<String>,
+    #[serde(rename = "twoFactorAuthentication", default)]
+    pub two_factor_authentication: Option<bool>,
 }
 
 //------------------------------------------------------------------------------
@@ -165,8 +337,27 @@ pub struct PublicKeyResponse {
     pub kid: String,
     #[serde(rename = "use")]
     pub _use: String,
+    pub alg: String,
     pub kty: String,
-    pub n: String,
-    pub e: String,
+    // RSA
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    // EC / OKP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
 }
 
+//------------------------------------------------------------------------------
+// JWK Set Response
+//------------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize)]
+pub struct JwkSetResponse {
+    pub keys: Vec<PublicKeyResponse>,
+}
@@ -0,0 +1,33 @@
+use openssl::hash::{hash, MessageDigest};
+use openssl::rand::rand_bytes;
+use rustc_serialize::base64::{self, ToBase64};
+use error::OAuthError;
+
+// Proof Key for Code Exchange (RFC 7636)
+
+pub const CHALLENGE_METHOD: &'static str = "S256";
+
+// 32 random bytes encode to a 43 character verifier, the minimum allowed
+const VERIFIER_BYTES: usize = 32;
+
+pub struct Pkce {
+    pub code_verifier: String,
+    pub code_challenge: String,
+}
+
+pub fn generate() -> Result<Pkce, OAuthError> {
+    let mut bytes = [0u8; VERIFIER_BYTES];
+    try!(rand_bytes(&mut bytes));
+    let code_verifier = bytes.to_base64(base64::URL_SAFE);
+    let code_challenge = try!(code_challenge(&code_verifier));
+    Ok(Pkce {
+        code_verifier: code_verifier,
+        code_challenge: code_challenge,
+    })
+}
+
+// BASE64URL(SHA256(ASCII(code_verifier)))
+pub fn code_challenge(code_verifier: &str) -> Result<String, OAuthError> {
+    let digest = try!(hash(MessageDigest::sha256(), code_verifier.as_bytes()));
+    Ok(digest.to_base64(base64::URL_SAFE))
+}
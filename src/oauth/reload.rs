@@ -0,0 +1,177 @@
+use libc;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use error::OAuthError;
+use super::settings::{self, DotEnv, Settings, SettingsError};
+use super::crypto::keystore::KeyStore;
+use super::id_token::IdTokenVerifier;
+use super::client::ProviderClient;
+
+// How often the watcher checks for SIGHUP and changed files
+const POLL_INTERVAL_MS: u64 = 1000;
+
+//--------------------------------------------------------------------------
+// Reloadable
+//--------------------------------------------------------------------------
+
+// A handle to managed state which can be swapped while requests are in
+// flight. Clones share the same value. Each request works on the snapshot
+// returned by current(), so a reload never changes settings halfway through
+// a handler.
+pub struct Reloadable<T> {
+    value: Arc<RwLock<Arc<T>>>,
+}
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Reloadable<T> {
+        Reloadable { value: self.value.clone() }
+    }
+}
+
+impl<T> Reloadable<T> {
+
+    pub fn new(value: T) -> Reloadable<T> {
+        Reloadable {
+            value: Arc::new(RwLock::new(Arc::new(value))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<T> {
+        self.value.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, value: T) {
+        *self.value.write().unwrap() = Arc::new(value);
+    }
+}
+
+//--------------------------------------------------------------------------
+// Reloader
+//--------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum ReloadError {
+    Settings(SettingsError),
+    Keys(OAuthError),
+    Client(OAuthError),
+}
+
+// Everything derived from Settings which a reload replaces together. The
+// provider client is rebuilt so a new timeout or retry count takes effect.
+// The Flow and Sessions are deliberately left out so pending logins and
+// sessions survive, which means FLOW_* and SESSION_* settings only take
+// effect after a restart.
+pub struct Reloader {
+    dotenv: DotEnv,
+    pub settings: Reloadable<Settings>,
+    pub keystore: Reloadable<KeyStore>,
+    pub id_tokens: Reloadable<IdTokenVerifier>,
+    pub client: Reloadable<ProviderClient>,
+}
+
+impl Reloader {
+
+    pub fn new(dotenv: DotEnv, settings: Settings, keystore: KeyStore, client: ProviderClient) -> Reloader {
+        let id_tokens = IdTokenVerifier::from_settings(&settings);
+        Reloader {
+            dotenv: dotenv,
+            settings: Reloadable::new(settings),
+            keystore: Reloadable::new(keystore),
+            id_tokens: Reloadable::new(id_tokens),
+            client: Reloadable::new(client),
+        }
+    }
+
+    // Re-reads the configuration and keys, swapping them in only once all
+    // of them loaded and validated. On error the old versions stay active.
+    pub fn reload(&self) -> Result<(), ReloadError> {
+        let settings = try!(settings::read_settings(&self.dotenv).map_err(ReloadError::Settings));
+        let keystore = try!(KeyStore::from_settings(&settings).map_err(ReloadError::Keys));
+        let id_tokens = IdTokenVerifier::from_settings(&settings);
+        let client = try!(ProviderClient::from_settings(&settings).map_err(ReloadError::Client));
+
+        self.settings.replace(settings);
+        self.keystore.replace(keystore);
+        self.id_tokens.replace(id_tokens);
+        self.client.replace(client);
+        Ok(())
+    }
+
+    // Reloads on SIGHUP or whenever one of the watched files changes, in a
+    // background thread owning this reloader.
+    pub fn watch(self) {
+        install_sighup_handler();
+
+        thread::spawn(move || {
+            let mut paths = watched_paths(&self.settings.current(), &self.dotenv);
+            let mut seen = modified_times(&paths);
+
+            loop {
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+
+                let hangup = SIGHUP_RECEIVED.swap(false, Ordering::SeqCst);
+                let modified = modified_times(&paths);
+                if !hangup && modified == seen {
+                    continue;
+                }
+
+                match self.reload() {
+                    Ok(()) => println!("Reloaded settings and keys ({})", if hangup { "SIGHUP" } else { "file change" }),
+                    Err(err) => println!("Reload failed, keeping the current settings and keys: {:?}", err),
+                }
+
+                // The new settings may point at other files
+                paths = watched_paths(&self.settings.current(), &self.dotenv);
+                seen = modified_times(&paths);
+            }
+        });
+    }
+}
+
+//--------------------------------------------------------------------------
+// Triggers
+//--------------------------------------------------------------------------
+
+static SIGHUP_RECEIVED: AtomicBool = ATOMIC_BOOL_INIT;
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+fn install_sighup_handler() {
+    unsafe {
+        libc::signal(libc::SIGHUP, on_sighup as libc::sighandler_t);
+    }
+}
+
+// The config files and key material the settings were read from
+pub fn watched_paths(settings: &Settings, dotenv: &DotEnv) -> Vec<PathBuf> {
+    let mut paths = vec![dotenv.path().to_path_buf()];
+
+    if let Ok(config_file) = env::var("CONFIG_FILE") {
+        paths.push(PathBuf::from(config_file));
+    }
+    let config_dir = env::var("CONFIG_DIR").unwrap_or("./config".to_string());
+    paths.push(Path::new(&config_dir).join(format!("{}.json", settings.profile.name())));
+
+    match settings.keystore_path {
+        Some(ref dir) => paths.push(Path::new(dir).join("keys.json")),
+        None => {
+            paths.push(PathBuf::from(&settings.public_key_path));
+            paths.push(PathBuf::from(&settings.secret_key_path));
+        },
+    }
+
+    paths
+}
+
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths.iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
@@ -0,0 +1,127 @@
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+use serde::{Serialize, Serializer};
+
+//--------------------------------------------------------------------------
+// Secret
+//--------------------------------------------------------------------------
+
+// A secret setting. The value is overwritten with zeros when dropped and is
+// never printed by Debug, so Settings can be logged safely.
+#[derive(Clone, Default)]
+pub struct Secret(String);
+
+impl Secret {
+
+    pub fn new(value: String) -> Secret {
+        Secret(value)
+    }
+
+    #[inline]
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        unsafe {
+            zeroize(self.0.as_mut_vec());
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret([redacted])")
+    }
+}
+
+// Serialized as the plain value, for the requests which have to send it
+impl Serialize for Secret {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+//--------------------------------------------------------------------------
+// SecretBytes
+//--------------------------------------------------------------------------
+
+// Like Secret, for binary keys
+#[derive(Clone, Default)]
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+
+    pub fn new(value: Vec<u8>) -> SecretBytes {
+        SecretBytes(value)
+    }
+
+    #[inline]
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes([redacted])")
+    }
+}
+
+fn zeroize(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe {
+            ptr::write_volatile(byte, 0);
+        }
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+//--------------------------------------------------------------------------
+// Indirection
+//--------------------------------------------------------------------------
+
+// Resolves `file:/path` (the file's contents, without the trailing newline)
+// and `env:OTHER_VAR` (another variable's value). Anything else is returned
+// as is. Errors name the indirection which failed.
+pub fn resolve(value: String) -> Result<String, String> {
+    if value.starts_with("file:") {
+        let path = &value["file:".len()..];
+        let mut contents = String::new();
+        return match File::open(path).and_then(|mut file| file.read_to_string(&mut contents)) {
+            Ok(_) => {
+                let len = contents.trim_right_matches(|c| c == '\n' || c == '\r').len();
+                contents.truncate(len);
+                Ok(contents)
+            },
+            Err(err) => Err(format!("file:{} could not be read ({})", path, err)),
+        };
+    }
+
+    if value.starts_with("env:") {
+        let name = &value["env:".len()..];
+        return env::var(name)
+            .map_err(|err| format!("env:{} could not be read ({})", name, err));
+    }
+
+    Ok(value)
+}
@@ -1,69 +1,684 @@
-use rocket::http::{CookieJar, Cookie};
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use rocket::Outcome;
+use rocket::State;
+use reqwest::Url;
+use rocket::http::{Status, CookieJar};
+use rocket::request::{self, Request, FromRequest};
+use rustc_serialize::base64::{self, ToBase64};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use serde_json;
+use std::time::Duration;
+use super::crypto::aead::{self, SessionCodec, MAX_CHUNKS};
+use super::flow::{now, random_string};
+use super::reload::Reloadable;
+use super::settings::Settings;
+use super::store::JsonFile;
+use super::{AccessResponse, TokenResponse};
+use super::client::ProviderClient;
+use error::OAuthError;
 
-pub struct Session(pub CookieJar<'static>);
+// Name of the cookie holding the signed session id, or the prefix of the
+// cookies holding a sealed session
+pub const COOKIE_NAME: &'static str = "session";
 
-fn build_cookie(name: String, value: String) -> Cookie<'static> { 
-    // It's oven time, baby
-    Cookie::build(name, value)
-        .domain("www.example.com")
-        .path("/api")
-        .http_only(true)
-        .finish()
+// Default lifetime of a session (one day)
+pub const DEFAULT_TTL: u64 = 86400;
+
+// Access tokens are renewed this many seconds before they expire
+pub const REFRESH_LEEWAY: u64 = 60;
+
+//--------------------------------------------------------------------------
+// SessionData
+//--------------------------------------------------------------------------
+
+// What we know about a logged in user. Only the session id ever reaches the
+// browser, the provider's tokens stay on the server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionData {
+    // Milliseconds since the unix epoch
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub uid: String,
+    pub email: String,
+    pub access_token: String,
+    pub token_type: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    // Must accompany state changing requests, see CsrfCheck
+    #[serde(default)]
+    pub csrf_token: String,
+    // With offline access, renews the access token before it expires at
+    // access_token_expires_at (milliseconds since the unix epoch)
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub access_token_expires_at: Option<u64>,
+    // The profile fetched at login or by /api/me?refresh=true
+    #[serde(default)]
+    pub profile: Option<AccessResponse>,
 }
 
-impl Session {
+impl SessionData {
 
-    pub fn new(key: &[u8]) -> Session {
-        Session(CookieJar::new(key))
+    pub fn new(ttl: &Duration, uid: String, email: String, access_token: String, token_type: String, scopes: Vec<String>) -> SessionData {
+        let created_at = now();
+        SessionData {
+            created_at: created_at,
+            expires_at: created_at + ttl.as_secs() * 1000,
+            uid: uid,
+            email: email,
+            access_token: access_token,
+            token_type: token_type,
+            scopes: scopes,
+            csrf_token: random_string(),
+            refresh_token: None,
+            access_token_expires_at: None,
+            profile: None,
+        }
     }
 
-    //----------------------------------------------------------------------
-    // Accessors
-    //----------------------------------------------------------------------
-
     #[inline]
-    pub fn state(&self) -> String {
-        let state_cookie = self.0.find("state").unwrap();
-        state_cookie.value().to_string()
+    pub fn set_refresh_token(mut self, refresh_token: Option<String>) -> SessionData {
+        self.refresh_token = refresh_token;
+        self
     }
 
     #[inline]
-    pub fn code(&self) -> String {
-        let code_cookie = self.0.find("code").unwrap();
-        code_cookie.value().to_string()
+    pub fn set_expires_in(mut self, expires_in: Option<u64>) -> SessionData {
+        self.access_token_expires_at = expires_in.map(|seconds| now() + seconds * 1000);
+        self
     }
 
-    //----------------------------------------------------------------------
-    // Mutators
-    //----------------------------------------------------------------------
-
     #[inline]
-    fn set_state(&mut self, state: String) {
-        self.0.add(build_cookie("state".to_string(), state));
+    pub fn set_profile(mut self, profile: Option<AccessResponse>) -> SessionData {
+        self.profile = profile;
+        self
     }
 
     #[inline]
-    fn remove_state(&mut self) {
-        self.0.remove(Cookie::named("state"));
+    pub fn is_expired(&self) -> bool {
+        now() >= self.expires_at
     }
 
-    #[inline]
-    fn set_code(&mut self, code: String) {
-        self.0.add(build_cookie("code".to_string(), code));
+    // Whether the access token expires within leeway seconds. Tokens
+    // without a known expiry are left alone.
+    pub fn needs_refresh(&self, leeway: u64) -> bool {
+        match self.access_token_expires_at {
+            Some(expires_at) => now() + leeway * 1000 >= expires_at,
+            None => false,
+        }
     }
 
-    #[inline]
-    fn set_scopes(&mut self, scopes: String) {
-        self.0.add(build_cookie("scopes".to_string(), scopes));
+    // Takes the tokens of a refresh, keeping the refresh token when the
+    // provider did not rotate it
+    pub fn renew(&mut self, token_rsp: TokenResponse) {
+        self.access_token = token_rsp.access_token;
+        self.token_type = token_rsp.token_type;
+        if !token_rsp.scopes.is_empty() {
+            self.scopes = token_rsp.scopes;
+        }
+        if token_rsp.refresh_token.is_some() {
+            self.refresh_token = token_rsp.refresh_token;
+        }
+        self.access_token_expires_at = token_rsp.expires_in.map(|seconds| now() + seconds * 1000);
     }
+}
 
-    #[inline]
-    fn set_token_type(&mut self, token_type: String) {
-        self.0.add(build_cookie("token_type".to_string(), token_type));
+fn sweep_expired(data: &mut HashMap<String, SessionData>) -> usize {
+    let before = data.len();
+    data.retain(|_, session| !session.is_expired());
+    before - data.len()
+}
+
+//--------------------------------------------------------------------------
+// SessionStore
+//--------------------------------------------------------------------------
+
+pub trait SessionStore: Send + Sync {
+    fn insert(&self, id: String, session: SessionData) -> Result<(), OAuthError>;
+
+    fn find(&self, id: &str) -> Result<Option<SessionData>, OAuthError>;
+
+    fn remove(&self, id: &str) -> Result<Option<SessionData>, OAuthError>;
+
+    // Evicts expired sessions, returning how many were removed
+    fn sweep(&self) -> Result<usize, OAuthError>;
+}
+
+pub struct MemorySessionStore(Mutex<HashMap<String, SessionData>>);
+
+impl MemorySessionStore {
+    pub fn new() -> MemorySessionStore {
+        MemorySessionStore(Mutex::new(HashMap::new()))
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn insert(&self, id: String, session: SessionData) -> Result<(), OAuthError> {
+        let mut data = self.0.lock().unwrap();
+        data.insert(id, session);
+        Ok(())
+    }
+
+    fn find(&self, id: &str) -> Result<Option<SessionData>, OAuthError> {
+        let data = self.0.lock().unwrap();
+        Ok(data.get(id).cloned())
+    }
+
+    fn remove(&self, id: &str) -> Result<Option<SessionData>, OAuthError> {
+        let mut data = self.0.lock().unwrap();
+        Ok(data.remove(id))
+    }
+
+    fn sweep(&self) -> Result<usize, OAuthError> {
+        let mut data = self.0.lock().unwrap();
+        Ok(sweep_expired(&mut data))
+    }
+}
+
+// Sessions in a JSON file, shared by every instance using the same path.
+// Records hold the user's tokens, so each is sealed under its id with a key
+// derived from the secret every instance signs ids with.
+pub struct FileSessionStore {
+    file: JsonFile<String>,
+    codec: SessionCodec,
+}
+
+impl FileSessionStore {
+
+    pub fn new(path: &str, secret: &[u8]) -> Result<FileSessionStore, OAuthError> {
+        let key = try!(PKey::hmac(secret));
+        let mut signer = try!(Signer::new(MessageDigest::sha256(), &key));
+        try!(signer.update(b"file-session-store"));
+
+        Ok(FileSessionStore {
+            file: JsonFile::new(path),
+            codec: try!(SessionCodec::new(vec![try!(signer.sign_to_vec())])),
+        })
+    }
+
+    // A record which fails to open, e.g. after SESSION_SECRET changed, is
+    // treated as gone
+    fn open(&self, id: &str, record: &str) -> Option<SessionData> {
+        self.codec.open(id, record)
+            .and_then(|json| serde_json::from_slice(&json).ok())
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn insert(&self, id: String, session: SessionData) -> Result<(), OAuthError> {
+        let record = try!(self.codec.seal(&id, &try!(serde_json::to_vec(&session))));
+        self.file.update(|data| { data.insert(id, record); })
+    }
+
+    fn find(&self, id: &str) -> Result<Option<SessionData>, OAuthError> {
+        let record = try!(self.file.read(|data| data.get(id).cloned()));
+        Ok(record.and_then(|record| self.open(id, &record)))
+    }
+
+    fn remove(&self, id: &str) -> Result<Option<SessionData>, OAuthError> {
+        let record = try!(self.file.update(|data| data.remove(id)));
+        Ok(record.and_then(|record| self.open(id, &record)))
+    }
+
+    fn sweep(&self) -> Result<usize, OAuthError> {
+        self.file.update(|data| {
+            let before = data.len();
+            data.retain(|id, record| {
+                self.open(id, record).map(|session| !session.is_expired()).unwrap_or(false)
+            });
+            before - data.len()
+        })
+    }
+}
+
+//--------------------------------------------------------------------------
+// Backends
+//--------------------------------------------------------------------------
+
+// Where sessions are kept
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionBackendKind {
+    // A store on the server, named by a signed id cookie
+    Server,
+    // Sealed into the cookies themselves, for stateless deployments
+    Cookie,
+}
+
+#[derive(Clone, Debug)]
+pub enum SessionStoreKind {
+    Memory,
+    File(String),
+}
+
+// A session loaded for the current request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    pub data: SessionData,
+}
+
+pub trait SessionBackend: Send + Sync {
+    // Keeps a new session and sets the cookies naming it
+    fn start(&self, settings: &Settings, jar: &CookieJar, data: SessionData) -> Result<Session, OAuthError>;
+
+    // The live session the request's cookies name, if any
+    fn current(&self, settings: &Settings, jar: &CookieJar) -> Result<Option<Session>, OAuthError>;
+
+    // Writes back a session changed by a handler
+    fn save(&self, settings: &Settings, jar: &CookieJar, session: &Session) -> Result<(), OAuthError>;
+
+    // Forgets the session and asks the browser to drop its cookies
+    fn end(&self, settings: &Settings, jar: &CookieJar, session: &Session) -> Result<(), OAuthError>;
+}
+
+//--------------------------------------------------------------------------
+// ServerSessions
+//--------------------------------------------------------------------------
+
+// Keeps sessions in a SessionStore. The cookie value is the session id and
+// an HMAC-SHA256 of it, so ids cannot be guessed or forged.
+pub struct ServerSessions {
+    store: Box<SessionStore>,
+    key: Vec<u8>,
+}
+
+impl ServerSessions {
+
+    pub fn new(store: Box<SessionStore>, key: Vec<u8>) -> ServerSessions {
+        ServerSessions {
+            store: store,
+            key: key,
+        }
+    }
+
+    // Without SESSION_SECRET a random key is used, so sessions end when the
+    // process restarts.
+    pub fn from_settings(settings: &Settings) -> Result<ServerSessions, OAuthError> {
+        let key = match settings.session_secret.is_empty() {
+            true => {
+                let mut key = vec![0u8; 32];
+                try!(rand_bytes(&mut key));
+                key
+            },
+            false => settings.session_secret.expose().as_bytes().to_vec(),
+        };
+
+        let store: Box<SessionStore> = match settings.session_store {
+            SessionStoreKind::Memory => Box::new(MemorySessionStore::new()),
+            SessionStoreKind::File(ref path) => Box::new(try!(FileSessionStore::new(path, &key))),
+        };
+
+        Ok(ServerSessions::new(store, key))
+    }
+
+    fn mac(&self, id: &str) -> Result<String, OAuthError> {
+        let key = try!(PKey::hmac(&self.key));
+        let mut signer = try!(Signer::new(MessageDigest::sha256(), &key));
+        try!(signer.update(id.as_bytes()));
+        Ok(try!(signer.sign_to_vec()).to_base64(base64::URL_SAFE))
+    }
+
+    // The cookie value for a session id
+    pub fn sign(&self, id: &str) -> Result<String, OAuthError> {
+        Ok(format!("{}.{}", id, try!(self.mac(id))))
+    }
+
+    // The session id in a cookie value, if its signature is ours
+    pub fn unsign(&self, value: &str) -> Result<Option<String>, OAuthError> {
+        let mut parts = value.rsplitn(2, '.');
+        let (mac, id) = match (parts.next(), parts.next()) {
+            (Some(mac), Some(id)) => (mac, id),
+            _ => return Ok(None),
+        };
+
+        let expected = try!(self.mac(id));
+        if expected.len() == mac.len() && memcmp::eq(expected.as_bytes(), mac.as_bytes()) {
+            Ok(Some(id.to_string()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Stores a new session, evicting expired ones, and returns the cookie
+    // value identifying it.
+    pub fn create(&self, data: SessionData) -> Result<(Session, String), OAuthError> {
+        let id = random_string();
+        try!(self.store.sweep());
+        try!(self.store.insert(id.clone(), data.clone()));
+        let value = try!(self.sign(&id));
+        Ok((Session { id: id, data: data }, value))
+    }
+
+    // Resolves a cookie value to a live session
+    pub fn load(&self, value: &str) -> Result<Option<Session>, OAuthError> {
+        let id = match try!(self.unsign(value)) {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        match try!(self.store.find(&id)) {
+            Some(ref data) if data.is_expired() => {
+                let _ = try!(self.store.remove(&id));
+                Ok(None)
+            },
+            Some(data) => Ok(Some(Session { id: id, data: data })),
+            None => Ok(None),
+        }
+    }
+
+    pub fn destroy(&self, session: &Session) -> Result<(), OAuthError> {
+        let _ = try!(self.store.remove(&session.id));
+        Ok(())
+    }
+}
+
+impl SessionBackend for ServerSessions {
+    fn start(&self, settings: &Settings, jar: &CookieJar, data: SessionData) -> Result<Session, OAuthError> {
+        let (session, value) = try!(self.create(data));
+        settings.cookie.add(jar, COOKIE_NAME, value);
+        Ok(session)
+    }
+
+    fn current(&self, settings: &Settings, jar: &CookieJar) -> Result<Option<Session>, OAuthError> {
+        match settings.cookie.find(jar, COOKIE_NAME) {
+            Some(value) => self.load(&value),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, _: &Settings, _: &CookieJar, session: &Session) -> Result<(), OAuthError> {
+        self.store.insert(session.id.clone(), session.data.clone())
+    }
+
+    fn end(&self, settings: &Settings, jar: &CookieJar, session: &Session) -> Result<(), OAuthError> {
+        try!(self.destroy(session));
+        settings.cookie.remove(jar, COOKIE_NAME);
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------
+// CookieSessions
+//--------------------------------------------------------------------------
+
+// Keeps the whole session in the browser, sealed with SESSION_KEYS and
+// split across session.0, session.1, ... when it outgrows one cookie.
+pub struct CookieSessions {
+    codec: SessionCodec,
+}
+
+fn chunk_name(index: usize) -> String {
+    format!("{}.{}", COOKIE_NAME, index)
+}
+
+impl CookieSessions {
+
+    pub fn new(codec: SessionCodec) -> CookieSessions {
+        CookieSessions { codec: codec }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<CookieSessions, OAuthError> {
+        Ok(CookieSessions::new(try!(SessionCodec::from_settings(settings))))
+    }
+
+    // The sealed value spread over the chunk cookies. Removed cookies are
+    // still in the jar with an empty value until the response is sent.
+    fn read(&self, settings: &Settings, jar: &CookieJar) -> Option<String> {
+        let mut value = String::new();
+        for index in 0..MAX_CHUNKS {
+            match settings.cookie.find(jar, &chunk_name(index)) {
+                Some(ref chunk) if !chunk.is_empty() => value.push_str(chunk),
+                _ => break,
+            }
+        }
+
+        if value.is_empty() { None } else { Some(value) }
+    }
+
+    // Removes the chunk cookies from index on which the browser sent
+    fn clear(&self, settings: &Settings, jar: &CookieJar, from: usize) {
+        for index in from..MAX_CHUNKS {
+            let name = chunk_name(index);
+            if settings.cookie.find(jar, &name).is_some() {
+                settings.cookie.remove(jar, &name);
+            }
+        }
+    }
+}
+
+impl SessionBackend for CookieSessions {
+    fn start(&self, settings: &Settings, jar: &CookieJar, data: SessionData) -> Result<Session, OAuthError> {
+        let session = Session { id: random_string(), data: data };
+        try!(self.save(settings, jar, &session));
+        Ok(session)
+    }
+
+    fn current(&self, settings: &Settings, jar: &CookieJar) -> Result<Option<Session>, OAuthError> {
+        let value = match self.read(settings, jar) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let session: Session = match self.codec.open(COOKIE_NAME, &value) {
+            Some(plaintext) => match serde_json::from_slice(&plaintext) {
+                Ok(session) => session,
+                Err(_) => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        if session.data.is_expired() {
+            self.clear(settings, jar, 0);
+            return Ok(None);
+        }
+
+        // Move sessions sealed with a retired key onto the current one
+        if !self.codec.is_current(&value) {
+            try!(self.save(settings, jar, &session));
+        }
+
+        Ok(Some(session))
+    }
+
+    fn save(&self, settings: &Settings, jar: &CookieJar, session: &Session) -> Result<(), OAuthError> {
+        let json = try!(serde_json::to_vec(session));
+        let value = try!(self.codec.seal(COOKIE_NAME, &json));
+        let chunks = try!(aead::split(&value));
+
+        let count = chunks.len();
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            settings.cookie.add(jar, &chunk_name(index), chunk);
+        }
+        self.clear(settings, jar, count);
+        Ok(())
+    }
+
+    fn end(&self, settings: &Settings, jar: &CookieJar, _: &Session) -> Result<(), OAuthError> {
+        self.clear(settings, jar, 0);
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------
+// Sessions
+//--------------------------------------------------------------------------
+
+// The configured backend, managed by Rocket for handlers and the guard
+pub struct Sessions {
+    backend: Box<SessionBackend>,
+    ttl: Duration,
+}
+
+impl Sessions {
+
+    pub fn new(backend: Box<SessionBackend>, ttl: Duration) -> Sessions {
+        Sessions {
+            backend: backend,
+            ttl: ttl,
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<Sessions, OAuthError> {
+        let backend: Box<SessionBackend> = match settings.session_backend {
+            SessionBackendKind::Server => Box::new(try!(ServerSessions::from_settings(settings))),
+            SessionBackendKind::Cookie => Box::new(try!(CookieSessions::from_settings(settings))),
+        };
+
+        Ok(Sessions::new(backend, Duration::from_secs(settings.session_ttl)))
     }
 
     #[inline]
-    fn set_token(&mut self, token: String) {
-        self.0.add(build_cookie("token".to_string(), token));
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn start(&self, settings: &Settings, jar: &CookieJar, data: SessionData) -> Result<Session, OAuthError> {
+        self.backend.start(settings, jar, data)
+    }
+
+    pub fn current(&self, settings: &Settings, jar: &CookieJar) -> Result<Option<Session>, OAuthError> {
+        self.backend.current(settings, jar)
+    }
+
+    pub fn save(&self, settings: &Settings, jar: &CookieJar, session: &Session) -> Result<(), OAuthError> {
+        self.backend.save(settings, jar, session)
+    }
+
+    pub fn end(&self, settings: &Settings, jar: &CookieJar, session: &Session) -> Result<(), OAuthError> {
+        self.backend.end(settings, jar, session)
+    }
+
+    // Renews the access token shortly before it expires. When that is not
+    // possible the session is ended and None returned, so the user logs in
+    // again instead of carrying a dead token around.
+    pub fn refresh(&self, settings: &Settings, client: &ProviderClient, jar: &CookieJar, session: Session) -> Result<Option<Session>, OAuthError> {
+        if !session.data.needs_refresh(REFRESH_LEEWAY) {
+            return Ok(Some(session));
+        }
+
+        let refreshed = match session.data.refresh_token.clone() {
+            Some(refresh_token) => client.refresh(settings, refresh_token),
+            None => Err(OAuthError::UnknownError("Access token expired without a refresh token".to_string())),
+        };
+
+        match refreshed {
+            Ok(token_rsp) => {
+                let mut session = session;
+                session.data.renew(token_rsp);
+                try!(self.save(settings, jar, &session));
+                Ok(Some(session))
+            },
+            Err(err) => {
+                println!("Ending session, could not refresh its access token: {}", err);
+                try!(self.end(settings, jar, &session));
+                Ok(None)
+            },
+        }
+    }
+
+    // The current session with a usable access token
+    pub fn current_refreshed(&self, settings: &Settings, client: &ProviderClient, jar: &CookieJar) -> Result<Option<Session>, OAuthError> {
+        match try!(self.current(settings, jar)) {
+            Some(session) => self.refresh(settings, client, jar, session),
+            None => Ok(None),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Request Guard
+//--------------------------------------------------------------------------
+
+// Loads the session named by the cookie, renewing its access token when it
+// is about to expire. Fails with 401 when there is no session or it could
+// not be renewed. Use Option<Session> for handlers which also serve
+// anonymous users.
+impl<'a, 'r> FromRequest<'a, 'r> for Session {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Session, ()> {
+        let settings = match State::<Reloadable<Settings>>::from_request(request) {
+            Outcome::Success(settings) => settings.current(),
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let sessions = match State::<Sessions>::from_request(request) {
+            Outcome::Success(sessions) => sessions,
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let client = match State::<Reloadable<ProviderClient>>::from_request(request) {
+            Outcome::Success(client) => client.current(),
+            _ => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+
+        match sessions.current_refreshed(&settings, &client, request.cookies()) {
+            Ok(Some(session)) => Outcome::Success(session),
+            Ok(None) => Outcome::Failure((Status::Unauthorized, ())),
+            Err(_) => Outcome::Failure((Status::InternalServerError, ())),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// CSRF
+//--------------------------------------------------------------------------
+
+// Where a state changing request came from and the X-CSRF-Token it carried.
+// Browsers send Origin with cross-site POSTs, Referer stands in for older
+// ones.
+pub struct CsrfCheck {
+    origin: Option<String>,
+    header_token: Option<String>,
+}
+
+fn url_origin(url: &str) -> Option<String> {
+    Url::parse(url).ok().map(|url| url.origin().ascii_serialization())
+}
+
+impl CsrfCheck {
+
+    pub fn new(origin: Option<String>, header_token: Option<String>) -> CsrfCheck {
+        CsrfCheck {
+            origin: origin,
+            header_token: header_token,
+        }
+    }
+
+    // The request must come from our own origin, that of REDIRECT_URI, and
+    // present the session's token in the header or the form.
+    pub fn verify(&self, settings: &Settings, session: &Session, form_token: Option<&str>) -> bool {
+        if let Some(ref origin) = self.origin {
+            if url_origin(&settings.redirect_uri).as_ref() != Some(origin) {
+                return false;
+            }
+        }
+
+        let expected = session.data.csrf_token.as_bytes();
+        let token = match form_token.or(self.header_token.as_ref().map(|token| token.as_str())) {
+            Some(token) => token.as_bytes(),
+            None => return false,
+        };
+        !expected.is_empty() && expected.len() == token.len() && memcmp::eq(expected, token)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for CsrfCheck {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<CsrfCheck, ()> {
+        let headers = request.headers();
+        let origin = match headers.get_one("Origin") {
+            // Sandboxed frames send "null", which never matches
+            Some(origin) => Some(origin.to_string()),
+            None => headers.get_one("Referer").and_then(url_origin),
+        };
+        let header_token = headers.get_one("X-CSRF-Token").map(|token| token.to_string());
+
+        Outcome::Success(CsrfCheck::new(origin, header_token))
     }
 }
@@ -1,43 +1,771 @@
-use dotenv::dotenv;
+use reqwest::Url;
+use serde_json::{self, Value};
+use std::collections::{BTreeMap, HashSet};
 use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use super::flow;
+use super::discovery::{self, Endpoints};
+use super::crypto::jwe::KeyUnlock;
+use super::secret::{self, Secret};
+use super::cookie::{CookiePolicy, SameSite};
+use super::session::{self, SessionBackendKind, SessionStoreKind};
+use super::crypto::aead;
+use super::client;
+use error::OAuthError;
 
 //--------------------------------------------------------------------------
 // Settings
 //--------------------------------------------------------------------------
 
+// Where pending logins (Flow states) are kept
+#[derive(Clone, Debug)]
+pub enum FlowStoreKind {
+    Memory,
+    File(String),
+}
+
 #[derive(Clone)]
 pub struct Settings {
     pub public_key_path: String,
     pub secret_key_path: String,
     pub client_id: String,
-    pub client_secret: String,
+    pub client_secret: Secret,
+    // Public clients (SPAs, mobile apps) rely on PKCE alone and never send
+    // the client secret.
+    pub public_client: bool,
+    // Ask for a refresh token so sessions outlive the access token
+    pub offline_access: bool,
     pub redirect_uri: String,
     pub oauth_uri: String,
     pub content_uri: String,
     pub profile_uri: String,
+    pub flow_ttl: u64,
+    pub flow_store: FlowStoreKind,
+    // Local paths a login may return to
+    pub return_to_allowlist: Vec<String>,
+    // Local paths or absolute URLs /api/logout may redirect to
+    pub logout_redirect_allowlist: Vec<String>,
+    // Claims of the session assertions we sign after a login
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    pub jwt_ttl: u64,
+    // Directory of rotated keys, see crypto::keystore
+    pub keystore_path: Option<String>,
+    // How long keys stay published before signing and after retiring
+    pub key_grace_period: u64,
+    // Unlocks encrypted secret key files
+    pub secret_key_unlock: Option<KeyUnlock>,
+    // Issuer to run OpenID Connect discovery against, if any
+    pub discovery_issuer: Option<String>,
+    pub discovery_cache_path: Option<String>,
+    // Provider endpoints, discovered or built from the URIs above
+    pub endpoints: Endpoints,
+    // Deployment profile the defaults below came from
+    pub profile: Profile,
+    // Attributes of the cookies we set
+    pub cookie: CookiePolicy,
+    // Sessions are kept on the server, identified by a cookie signed with
+    // the secret, or sealed into cookies with the first of the keys
+    pub session_backend: SessionBackendKind,
+    pub session_ttl: u64,
+    pub session_store: SessionStoreKind,
+    pub session_secret: Secret,
+    pub session_keys: Vec<Secret>,
+    // Calls to the provider give up after provider_timeout seconds and are
+    // repeated up to provider_retries times, token requests only when the
+    // connection was refused
+    pub provider_timeout: u64,
+    pub provider_retries: u64,
+    // Bearer token /api/diagnostics asks for, it answers nobody without one
+    pub diagnostics_token: Secret,
+}
+
+//--------------------------------------------------------------------------
+// Profile
+//--------------------------------------------------------------------------
+
+// Named deployments, selected with --profile or APP_PROFILE (falling back to
+// ROCKET_ENV). Each supplies defaults which any other source overrides.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    Development,
+    Staging,
+    Production,
+}
+
+const DEVELOPMENT_DEFAULTS: &'static [(&'static str, &'static str)] = &[
+    ("OAUTH_URI", "https://oauth-stable.dev.lcip.org/v1"),
+    ("CONTENT_URI", "https://stable.dev.lcip.org"),
+    ("PROFILE_URI", "https://stable.dev.lcip.org/profile/v1"),
+    ("PUBLIC_KEY_PATH", "./priv/public-key.json"),
+    ("SECRET_KEY_PATH", "./priv/secret-key.json"),
+    ("COOKIE_DOMAIN", "localhost"),
+    ("COOKIE_SECURE", "false"),
+];
+
+const STAGING_DEFAULTS: &'static [(&'static str, &'static str)] = &[
+    ("OAUTH_URI", "https://oauth.stage.mozaws.net/v1"),
+    ("CONTENT_URI", "https://accounts.stage.mozaws.net"),
+    ("PROFILE_URI", "https://profile.stage.mozaws.net/v1"),
+    ("PUBLIC_KEY_PATH", "./priv/staging/public-key.json"),
+    ("SECRET_KEY_PATH", "./priv/staging/secret-key.json"),
+    ("COOKIE_SECURE", "true"),
+];
+
+const PRODUCTION_DEFAULTS: &'static [(&'static str, &'static str)] = &[
+    ("OAUTH_URI", "https://oauth.accounts.firefox.com/v1"),
+    ("CONTENT_URI", "https://accounts.firefox.com"),
+    ("PROFILE_URI", "https://profile.accounts.firefox.com/v1"),
+    ("PUBLIC_KEY_PATH", "/etc/example-application/public-key.json"),
+    ("SECRET_KEY_PATH", "/etc/example-application/secret-key.json"),
+    ("COOKIE_SECURE", "true"),
+];
+
+impl Profile {
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Profile::Development => "development",
+            Profile::Staging => "staging",
+            Profile::Production => "production",
+        }
+    }
+
+    // Also accepts Rocket's dev / stage / prod
+    pub fn from_name(name: &str) -> Option<Profile> {
+        match name {
+            "development" | "dev" => Some(Profile::Development),
+            "staging" | "stage" => Some(Profile::Staging),
+            "production" | "prod" => Some(Profile::Production),
+            _ => None,
+        }
+    }
+
+    pub fn default_value(&self, name: &str) -> Option<String> {
+        let defaults = match *self {
+            Profile::Development => DEVELOPMENT_DEFAULTS,
+            Profile::Staging => STAGING_DEFAULTS,
+            Profile::Production => PRODUCTION_DEFAULTS,
+        };
+        defaults.iter()
+            .find(|&&(key, _)| key == name)
+            .map(|&(_, value)| value.to_string())
+    }
+}
+
+//--------------------------------------------------------------------------
+// SettingsError
+//--------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum SettingsError {
+    // The config file could not be read or is not a JSON object
+    File(String, String),
+    // Every problem found while loading or validating
+    Invalid(Vec<String>),
+    Discovery(OAuthError),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SettingsError::File(ref path, ref reason) => write!(f, "Unable to read config file {}: {}", path, reason),
+            SettingsError::Invalid(ref problems) => {
+                try!(write!(f, "Invalid settings:"));
+                for problem in problems.iter() {
+                    try!(write!(f, "\n  - {}", problem));
+                }
+                Ok(())
+            },
+            SettingsError::Discovery(ref err) => write!(f, "Unable to discover OpenID Connect endpoints: {}", err),
+        }
+    }
+}
+
+impl Error for SettingsError {
+    fn description(&self) -> &str {
+        match *self {
+            SettingsError::File(..) => "unreadable config file",
+            SettingsError::Invalid(..) => "invalid settings",
+            SettingsError::Discovery(..) => "discovery failed",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            SettingsError::Discovery(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// Validation
+//--------------------------------------------------------------------------
+
+fn check_url(problems: &mut Vec<String>, name: &str, value: &str) {
+    match Url::parse(value) {
+        Ok(ref url) if url.scheme() == "https" || url.scheme() == "http" => {},
+        Ok(ref url) => problems.push(format!("{} must be an http(s) URL, not {}", name, url.scheme())),
+        Err(err) => problems.push(format!("{} is not a valid URL ({}): {:?}", name, err, value)),
+    }
+}
+
+fn check_readable(problems: &mut Vec<String>, name: &str, path: &str) {
+    if let Err(err) = File::open(path) {
+        problems.push(format!("{} {:?} is not readable: {}", name, path, err));
+    }
+}
+
+impl Settings {
+
+    // Reports every problem at once rather than stopping at the first
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let mut problems = Vec::new();
+
+        if self.client_id.trim().is_empty() {
+            problems.push("CLIENT_ID must not be empty".to_string());
+        }
+        if !self.public_client && self.client_secret.is_empty() {
+            problems.push("CLIENT_SECRET must not be empty unless PUBLIC_CLIENT=true".to_string());
+        }
+
+        check_url(&mut problems, "REDIRECT_URI", &self.redirect_uri);
+        check_url(&mut problems, "OAUTH_URI", &self.oauth_uri);
+        check_url(&mut problems, "CONTENT_URI", &self.content_uri);
+        check_url(&mut problems, "PROFILE_URI", &self.profile_uri);
+        check_url(&mut problems, "authorization endpoint", &self.endpoints.authorization);
+        check_url(&mut problems, "token endpoint", &self.endpoints.token);
+        check_url(&mut problems, "userinfo endpoint", &self.endpoints.userinfo);
+        check_url(&mut problems, "JWKS_URI", &self.endpoints.jwks);
+        if let Some(ref revocation) = self.endpoints.revocation {
+            check_url(&mut problems, "revocation endpoint", revocation);
+        }
+
+        if self.is_production() && !self.redirect_uri.starts_with("https://") {
+            problems.push("REDIRECT_URI must use https in production".to_string());
+        }
+
+        match self.keystore_path {
+            Some(ref keystore_path) => {
+                let manifest = Path::new(keystore_path).join("keys.json");
+                check_readable(&mut problems, "KEYSTORE_PATH manifest", manifest.to_str().unwrap_or(keystore_path));
+            },
+            None => {
+                check_readable(&mut problems, "PUBLIC_KEY_PATH", &self.public_key_path);
+                check_readable(&mut problems, "SECRET_KEY_PATH", &self.secret_key_path);
+            },
+        }
+
+        if let FlowStoreKind::File(ref path) = self.flow_store {
+            let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+            let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+            if !fs::metadata(dir).map(|meta| meta.is_dir()).unwrap_or(false) {
+                problems.push(format!("FLOW_STORE_PATH directory {:?} does not exist", dir));
+            }
+        }
+
+        if self.flow_ttl == 0 {
+            problems.push("FLOW_TTL must be greater than zero".to_string());
+        }
+        if self.jwt_ttl == 0 {
+            problems.push("JWT_TTL must be greater than zero".to_string());
+        }
+
+        if self.session_backend == SessionBackendKind::Cookie && self.session_keys.is_empty() {
+            problems.push("SESSION_KEYS required when SESSION_BACKEND=cookie".to_string());
+        }
+        for (index, key) in self.session_keys.iter().enumerate() {
+            if aead::decode_key(key.expose()).is_err() {
+                problems.push(format!("SESSION_KEYS entry {} must be a base64url encoded 256-bit key", index + 1));
+            }
+        }
+
+        if let (&SessionBackendKind::Server, &SessionStoreKind::File(ref path)) = (&self.session_backend, &self.session_store) {
+            let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+            let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+            if !fs::metadata(dir).map(|meta| meta.is_dir()).unwrap_or(false) {
+                problems.push(format!("SESSION_STORE_PATH directory {:?} does not exist", dir));
+            }
+            // Every instance sharing the file must sign ids with the same key
+            if self.session_secret.is_empty() {
+                problems.push("SESSION_SECRET required when SESSION_STORE=file".to_string());
+            }
+        }
+        if self.session_ttl == 0 {
+            problems.push("SESSION_TTL must be greater than zero".to_string());
+        }
+        if self.provider_timeout == 0 {
+            problems.push("PROVIDER_TIMEOUT must be greater than zero".to_string());
+        }
+
+        problems.extend(self.cookie.problems());
+
+        for path in self.return_to_allowlist.iter() {
+            if !path.starts_with('/') || path.starts_with("//") {
+                problems.push(format!("RETURN_TO_ALLOWLIST entry {:?} must be a local path", path));
+            }
+        }
+
+        for target in self.logout_redirect_allowlist.iter() {
+            let local = target.starts_with('/') && !target.starts_with("//");
+            let absolute = Url::parse(target)
+                .map(|url| url.scheme() == "https" || url.scheme() == "http")
+                .unwrap_or(false);
+            if !local && !absolute {
+                problems.push(format!("LOGOUT_REDIRECT_ALLOWLIST entry {:?} must be a local path or URL", target));
+            }
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(SettingsError::Invalid(problems)),
+        }
+    }
+
+    #[inline]
+    pub fn is_production(&self) -> bool {
+        self.profile == Profile::Production
+    }
+
+    // Only relative paths on this origin which appear in the allow-list are
+    // accepted, anything else would make us an open redirector.
+    pub fn allows_return_to(&self, return_to: &str) -> bool {
+        local_path_allowed(&self.return_to_allowlist, return_to)
+    }
+
+    // Local paths are matched like return_to, URLs must match exactly
+    pub fn allows_logout_redirect(&self, target: &str) -> bool {
+        match target.starts_with('/') {
+            true => local_path_allowed(&self.logout_redirect_allowlist, target),
+            false => self.logout_redirect_allowlist.iter().any(|allowed| allowed == target),
+        }
+    }
+}
+
+// Whether target is a path on this site whose path part is on the
+// allowlist. Rejects protocol relative //host and /\host, which browsers
+// treat as other sites.
+fn local_path_allowed(allowlist: &[String], target: &str) -> bool {
+    if !target.starts_with('/') || target.starts_with("//") || target.contains('\\') {
+        return false;
+    }
+
+    let path = target.split(|c| c == '?' || c == '#').next().unwrap_or("");
+    allowlist.iter().any(|allowed| allowed == path)
 }
 
-pub fn read_settings() -> Settings {
-    dotenv().ok();
-
-    let public_key_path: String = env::var("PUBLIC_KEY_PATH")
-        .expect("PUBLIC_KEY_PATH required");
-    let secret_key_path: String = env::var("SECRET_KEY_PATH")
-        .expect("SECRET_KEY_PATH required");
-    let client_id: String = env::var("CLIENT_ID")
-        .expect("CLIENT_ID required");
-    let redirect_uri: String = env::var("REDIRECT_URI")
-        .expect("REDIRECT_URI required");
-    let oauth_uri: String = env::var("OAUTH_URI")
-        .expect("OAUTH_URI required");
-    let content_uri: String = env::var("CONTENT_URI")
-        .expect("CONTENT_URI required");
-    let profile_uri: String = env::var("PROFILE_URI")
-        .expect("PROFILE_URI required");
-    let client_secret: String = env::var("CLIENT_SECRET")
-        .expect("CLIENT_SECRET required");
-
-    Settings {
+//--------------------------------------------------------------------------
+// Loading
+//--------------------------------------------------------------------------
+
+// Where settings come from, in order of precedence: --profile style command
+// line overrides, environment variables, the JSON config file named by
+// CONFIG_FILE, the profile's file in CONFIG_DIR (e.g. config/staging.json)
+// and finally the profile's built-in defaults. Files hold an object keyed
+// by the lowercase variable names, e.g. {"client_id": "...", "flow_ttl": 600}.
+pub struct Source {
+    overrides: BTreeMap<String, String>,
+    file: BTreeMap<String, Value>,
+    profile_file: BTreeMap<String, Value>,
+    use_env: bool,
+}
+
+fn file_value(value: &Value) -> Option<String> {
+    match *value {
+        Value::String(ref value) => Some(value.clone()),
+        Value::Bool(value) => Some(value.to_string()),
+        Value::Number(ref value) => Some(value.to_string()),
+        Value::Array(ref values) => Some(values.iter()
+            .filter_map(file_value)
+            .collect::<Vec<String>>()
+            .join(",")),
+        _ => None,
+    }
+}
+
+impl Source {
+
+    pub fn from_env() -> Result<Source, SettingsError> {
+        let file = match env::var("CONFIG_FILE") {
+            Ok(path) => try!(read_config_file(&path)),
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(Source { overrides: BTreeMap::new(), file: file, profile_file: BTreeMap::new(), use_env: true })
+    }
+
+    // A config document alone, ignoring the environment
+    pub fn from_json(json: &str) -> Result<Source, SettingsError> {
+        let file: BTreeMap<String, Value> = try!(serde_json::from_str(json)
+            .map_err(|err| SettingsError::File("<inline>".to_string(), format!("{}", err))));
+        Ok(Source { overrides: BTreeMap::new(), file: file, profile_file: BTreeMap::new(), use_env: false })
+    }
+
+    pub fn set(&mut self, name: &str, value: String) {
+        self.overrides.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.overrides.get(name) {
+            return Some(value.clone());
+        }
+        if self.use_env {
+            if let Ok(value) = env::var(name) {
+                return Some(value);
+            }
+        }
+        let key = name.to_lowercase();
+        self.file.get(&key)
+            .or(self.profile_file.get(&key))
+            .and_then(file_value)
+    }
+
+    // APP_PROFILE, then ROCKET_ENV, defaulting to development
+    pub fn profile(&self) -> Result<Profile, SettingsError> {
+        let name = match self.get("APP_PROFILE").or(self.get("ROCKET_ENV")) {
+            Some(name) => name,
+            None => return Ok(Profile::Development),
+        };
+        Profile::from_name(&name)
+            .ok_or(SettingsError::Invalid(vec![format!("Unknown profile {}, expected development, staging or production", name)]))
+    }
+
+    // Reads CONFIG_DIR/<profile>.json when it exists
+    pub fn load_profile_file(&mut self, profile: Profile) -> Result<(), SettingsError> {
+        let dir = self.get("CONFIG_DIR").unwrap_or("./config".to_string());
+        let path = Path::new(&dir).join(format!("{}.json", profile.name()));
+        if path.exists() {
+            self.profile_file = try!(read_config_file(path.to_str().unwrap_or(&dir)));
+        }
+        Ok(())
+    }
+}
+
+fn read_config_file(path: &str) -> Result<BTreeMap<String, Value>, SettingsError> {
+    let mut contents = String::new();
+    try!(File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|err| SettingsError::File(path.to_string(), format!("{}", err))));
+    serde_json::from_str(&contents)
+        .map_err(|err| SettingsError::File(path.to_string(), format!("{}", err)))
+}
+
+// Collects problems instead of stopping at the first one
+struct Loader<'a> {
+    source: &'a Source,
+    profile: Profile,
+    problems: Vec<String>,
+}
+
+impl<'a> Loader<'a> {
+
+    // Values may point elsewhere with file:/path or env:OTHER_VAR
+    fn optional(&mut self, name: &str) -> Option<String> {
+        let value = match self.source.get(name).or(self.profile.default_value(name)) {
+            Some(value) => value,
+            None => return None,
+        };
+        match secret::resolve(value) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.problems.push(format!("{}: {}", name, err));
+                None
+            },
+        }
+    }
+
+    fn required(&mut self, name: &str) -> String {
+        let unresolved = self.problems.len();
+        match self.optional(name) {
+            Some(value) => value,
+            None => {
+                // A failed indirection has already been reported
+                if self.problems.len() == unresolved {
+                    self.problems.push(format!("{} required", name));
+                }
+                String::new()
+            },
+        }
+    }
+
+    // A whole number of unit, e.g. "seconds"
+    fn number(&mut self, name: &str, unit: &str, default: u64) -> u64 {
+        match self.optional(name) {
+            Some(value) => match value.parse() {
+                Ok(value) => value,
+                Err(_) => {
+                    self.problems.push(format!("{} must be a number of {}, not {:?}", name, unit, value));
+                    default
+                },
+            },
+            None => default,
+        }
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        self.optional(name)
+            .map(|value| value == "true")
+            .unwrap_or(false)
+    }
+
+    // With discovery the provider URIs are optional, the endpoints come from
+    // the issuer's openid-configuration.
+    fn provider_uri(&mut self, name: &str, discovery_issuer: Option<&String>) -> String {
+        match (self.optional(name), discovery_issuer) {
+            (Some(uri), _) => uri,
+            (None, Some(issuer)) => issuer.clone(),
+            (None, None) => self.required(name),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------
+// .env
+//--------------------------------------------------------------------------
+
+// The .env file in the working directory. Variables the process was started
+// with win over it. Unlike dotenv(), loading it again replaces the values
+// an earlier load set, so edits are picked up on reload.
+pub struct DotEnv {
+    path: PathBuf,
+    inherited: HashSet<String>,
+}
+
+impl DotEnv {
+
+    // Call before anything sets variables, to tell inherited ones apart
+    pub fn new() -> DotEnv {
+        DotEnv {
+            path: PathBuf::from(".env"),
+            inherited: env::vars().map(|(name, _)| name).collect(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // A missing file is fine, every setting can come from the environment
+    pub fn load(&self) -> Result<(), SettingsError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return Ok(()),
+        };
+        let path = self.path.to_string_lossy().into_owned();
+
+        for line in BufReader::new(file).lines() {
+            let line = try!(line.map_err(|err| SettingsError::File(path.clone(), format!("{}", err))));
+            if let Some((name, value)) = parse_dotenv_line(&line) {
+                if !self.inherited.contains(&name) {
+                    env::set_var(&name, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// NAME=value, optionally preceded by export. Blank lines and comments are
+// None, like anything else which is not an assignment.
+pub fn parse_dotenv_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let line = match line.starts_with("export ") {
+        true => line["export ".len()..].trim_left(),
+        false => line,
+    };
+    let split = match line.find('=') {
+        Some(split) => split,
+        None => return None,
+    };
+    let (name, value) = (line[..split].trim(), line[split + 1..].trim());
+    if name.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), value.to_string()))
+}
+
+// Loads dotenv first, so the settings see its current values
+pub fn read_settings(dotenv: &DotEnv) -> Result<Settings, SettingsError> {
+    try!(dotenv.load());
+
+    let mut source = try!(Source::from_env());
+    if let Some(profile) = profile_argument() {
+        source.set("APP_PROFILE", profile);
+    }
+    let profile = try!(source.profile());
+    try!(source.load_profile_file(profile));
+    load(&source)
+}
+
+// The value of a --profile command line flag
+fn profile_argument() -> Option<String> {
+    let args: Vec<String> = env::args().collect();
+    args.iter()
+        .position(|arg| arg == "--profile")
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
+
+pub fn load(source: &Source) -> Result<Settings, SettingsError> {
+    let profile = try!(source.profile());
+    let mut loader = Loader { source: source, profile: profile, problems: Vec::new() };
+
+    let public_key_path: String = loader.required("PUBLIC_KEY_PATH");
+    let secret_key_path: String = loader.required("SECRET_KEY_PATH");
+    let client_id: String = loader.required("CLIENT_ID");
+    let redirect_uri: String = loader.required("REDIRECT_URI");
+    let discovery_issuer: Option<String> = loader.optional("OIDC_ISSUER");
+    let discovery_cache_path: Option<String> = loader.optional("DISCOVERY_CACHE_PATH");
+    let oauth_uri: String = loader.provider_uri("OAUTH_URI", discovery_issuer.as_ref());
+    let content_uri: String = loader.provider_uri("CONTENT_URI", discovery_issuer.as_ref());
+    let profile_uri: String = loader.provider_uri("PROFILE_URI", discovery_issuer.as_ref());
+    let public_client: bool = loader.flag("PUBLIC_CLIENT");
+    let offline_access: bool = loader.flag("OFFLINE_ACCESS");
+    let client_secret: Secret = Secret::new(match public_client {
+        true => loader.optional("CLIENT_SECRET").unwrap_or(String::new()),
+        false => loader.required("CLIENT_SECRET"),
+    });
+    let flow_ttl: u64 = loader.number("FLOW_TTL", "seconds", flow::DEFAULT_TTL);
+    let flow_store = match loader.optional("FLOW_STORE").unwrap_or("memory".to_string()).as_str() {
+        "memory" => FlowStoreKind::Memory,
+        "file" => match loader.optional("FLOW_STORE_PATH") {
+            Some(path) => FlowStoreKind::File(path),
+            None => {
+                loader.problems.push("FLOW_STORE_PATH required when FLOW_STORE=file".to_string());
+                FlowStoreKind::Memory
+            },
+        },
+        other => {
+            loader.problems.push(format!("Unknown FLOW_STORE {}, expected memory or file", other));
+            FlowStoreKind::Memory
+        },
+    };
+    let return_to_allowlist: Vec<String> = loader.optional("RETURN_TO_ALLOWLIST")
+        .unwrap_or("/,/iframe".to_string())
+        .split(',')
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty())
+        .collect();
+    let logout_redirect_allowlist: Vec<String> = loader.optional("LOGOUT_REDIRECT_ALLOWLIST")
+        .unwrap_or("/".to_string())
+        .split(',')
+        .map(|target| target.trim().to_string())
+        .filter(|target| !target.is_empty())
+        .collect();
+    let jwt_issuer: String = loader.optional("JWT_ISSUER")
+        .unwrap_or(redirect_uri.clone());
+    let jwt_audience: String = loader.optional("JWT_AUDIENCE")
+        .unwrap_or(client_id.clone());
+    let jwt_ttl: u64 = loader.number("JWT_TTL", "seconds", 3600);
+    let keystore_path: Option<String> = loader.optional("KEYSTORE_PATH");
+    let key_grace_period: u64 = loader.number("KEY_GRACE_PERIOD", "seconds", 86400);
+    let secret_key_unlock: Option<KeyUnlock> = match KeyUnlock::from_env() {
+        Ok(unlock) => unlock,
+        Err(err) => {
+            loader.problems.push(format!("Unable to read secret key passphrase or KEK: {}", err));
+            None
+        },
+    };
+    let discovery_cache_ttl: u64 = loader.number("DISCOVERY_CACHE_TTL", "seconds", discovery::DEFAULT_CACHE_TTL);
+    let cookie_domain: Option<String> = loader.optional("COOKIE_DOMAIN")
+        .and_then(|domain| if domain.is_empty() { None } else { Some(domain) });
+    let cookie_path: String = loader.optional("COOKIE_PATH")
+        .unwrap_or("/api".to_string());
+    // Never send session cookies over plain http in production
+    let cookie_secure: bool = profile == Profile::Production || loader.flag("COOKIE_SECURE");
+    let cookie_same_site: Option<SameSite> = match loader.optional("COOKIE_SAME_SITE") {
+        None => Some(SameSite::Lax),
+        Some(ref name) if name.is_empty() || name == "unset" => None,
+        Some(name) => match SameSite::from_name(&name) {
+            Some(same_site) => Some(same_site),
+            None => {
+                loader.problems.push(format!("Unknown COOKIE_SAME_SITE {}, expected strict, lax, none or unset", name));
+                None
+            },
+        },
+    };
+    let cookie_max_age: Option<u64> = match loader.optional("COOKIE_MAX_AGE") {
+        Some(_) => Some(loader.number("COOKIE_MAX_AGE", "seconds", 0)),
+        None => None,
+    };
+    let cookie_prefix: String = loader.optional("COOKIE_PREFIX")
+        .unwrap_or(String::new());
+    let session_backend = match loader.optional("SESSION_BACKEND").unwrap_or("server".to_string()).as_str() {
+        "server" => SessionBackendKind::Server,
+        "cookie" => SessionBackendKind::Cookie,
+        other => {
+            loader.problems.push(format!("Unknown SESSION_BACKEND {}, expected server or cookie", other));
+            SessionBackendKind::Server
+        },
+    };
+    let session_ttl: u64 = loader.number("SESSION_TTL", "seconds", session::DEFAULT_TTL);
+    let session_store = match loader.optional("SESSION_STORE").unwrap_or("memory".to_string()).as_str() {
+        "memory" => SessionStoreKind::Memory,
+        "file" => match loader.optional("SESSION_STORE_PATH") {
+            Some(path) => SessionStoreKind::File(path),
+            None => {
+                loader.problems.push("SESSION_STORE_PATH required when SESSION_STORE=file".to_string());
+                SessionStoreKind::Memory
+            },
+        },
+        other => {
+            loader.problems.push(format!("Unknown SESSION_STORE {}, expected memory or file", other));
+            SessionStoreKind::Memory
+        },
+    };
+    let session_secret: Secret = Secret::new(loader.optional("SESSION_SECRET")
+        .unwrap_or(String::new()));
+    // Comma or whitespace separated, newest first. A file: indirection may
+    // hold one key per line.
+    let session_keys: Vec<Secret> = loader.optional("SESSION_KEYS")
+        .map(|keys| {
+            keys.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|key| !key.is_empty())
+                .map(|key| Secret::new(key.to_string()))
+                .collect()
+        })
+        .unwrap_or(Vec::new());
+    let oauth_issuer: Option<String> = loader.optional("OAUTH_ISSUER");
+    let jwks_uri: Option<String> = loader.optional("JWKS_URI");
+    let provider_timeout: u64 = loader.number("PROVIDER_TIMEOUT", "seconds", client::DEFAULT_TIMEOUT);
+    let provider_retries: u64 = loader.number("PROVIDER_RETRIES", "retries", client::DEFAULT_RETRIES);
+    let diagnostics_token: Secret = Secret::new(loader.optional("DIAGNOSTICS_TOKEN").unwrap_or(String::new()));
+
+    // Discovery needs the rest to be sound, so report what we have first
+    if !loader.problems.is_empty() {
+        return Err(SettingsError::Invalid(loader.problems));
+    }
+
+    let endpoints: Endpoints = match discovery_issuer {
+        Some(ref issuer) => {
+            // Settings are not there yet to build the managed client from
+            let client = try!(client::ProviderClient::new(Duration::from_secs(provider_timeout), provider_retries,
+                                                          Duration::from_millis(client::DEFAULT_BACKOFF_MS))
+                .map_err(SettingsError::Discovery));
+            let metadata = try!(discovery::discover(&client, issuer, discovery_cache_path.as_ref().map(Path::new), discovery_cache_ttl)
+                .map_err(SettingsError::Discovery));
+            Endpoints::from_metadata(&metadata, &profile_uri)
+        },
+        None => {
+            let oauth_issuer: String = oauth_issuer
+                .unwrap_or(content_uri.trim_right_matches('/').to_string());
+            let mut endpoints = Endpoints::from_uris(&oauth_uri, &profile_uri, &oauth_issuer);
+            if let Some(jwks_uri) = jwks_uri {
+                endpoints.jwks = jwks_uri;
+            }
+            endpoints
+        },
+    };
+
+    let settings = Settings {
         public_key_path: public_key_path,
         secret_key_path: secret_key_path,
         client_id: client_id,
@@ -46,5 +774,40 @@ pub fn read_settings() -> Settings {
         content_uri: content_uri,
         profile_uri: profile_uri,
         client_secret: client_secret,
-    }
+        public_client: public_client,
+        offline_access: offline_access,
+        flow_ttl: flow_ttl,
+        flow_store: flow_store,
+        return_to_allowlist: return_to_allowlist,
+        logout_redirect_allowlist: logout_redirect_allowlist,
+        jwt_issuer: jwt_issuer,
+        jwt_audience: jwt_audience,
+        jwt_ttl: jwt_ttl,
+        keystore_path: keystore_path,
+        key_grace_period: key_grace_period,
+        secret_key_unlock: secret_key_unlock,
+        discovery_issuer: discovery_issuer,
+        discovery_cache_path: discovery_cache_path,
+        endpoints: endpoints,
+        profile: profile,
+        cookie: CookiePolicy {
+            domain: cookie_domain,
+            path: cookie_path,
+            secure: cookie_secure,
+            same_site: cookie_same_site,
+            max_age: cookie_max_age,
+            prefix: cookie_prefix,
+        },
+        session_backend: session_backend,
+        session_ttl: session_ttl,
+        session_store: session_store,
+        session_secret: session_secret,
+        session_keys: session_keys,
+        provider_timeout: provider_timeout,
+        provider_retries: provider_retries,
+        diagnostics_token: diagnostics_token,
+    };
+
+    try!(settings.validate());
+    Ok(settings)
 }
@@ -0,0 +1,142 @@
+use serde::{Serialize, Deserialize};
+use serde_json;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use error::OAuthError;
+
+// How long to wait for another process to release the lock file
+const LOCK_ATTEMPTS: u32 = 500;
+const LOCK_RETRY_MS: u64 = 10;
+
+// A lock file older than this is assumed to belong to a crashed process
+const STALE_LOCK_SECS: u64 = 30;
+
+//--------------------------------------------------------------------------
+// JsonFile
+//--------------------------------------------------------------------------
+
+// A map persisted as a single JSON document. Every access re-reads the file
+// under an exclusive lock file so that several processes sharing the same
+// path (e.g. on a network volume) observe each other's writes.
+pub struct JsonFile<V> {
+    path: PathBuf,
+    lock: Mutex<()>,
+    _value: PhantomData<V>,
+}
+
+struct LockFile(PathBuf);
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn is_stale(path: &Path) -> bool {
+    match fs::metadata(path).and_then(|meta| meta.modified()) {
+        Ok(modified) => match SystemTime::now().duration_since(modified) {
+            Ok(age) => age > Duration::from_secs(STALE_LOCK_SECS),
+            Err(_) => false,
+        },
+        Err(_) => false,
+    }
+}
+
+fn acquire(path: &Path) -> Result<LockFile, OAuthError> {
+    let lock_path = path.with_extension("lock");
+
+    for _ in 0..LOCK_ATTEMPTS {
+        match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+            Ok(_) => return Ok(LockFile(lock_path)),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                if is_stale(&lock_path) {
+                    let _ = fs::remove_file(&lock_path);
+                } else {
+                    thread::sleep(Duration::from_millis(LOCK_RETRY_MS));
+                }
+            },
+            Err(err) => return Err(OAuthError::from(err)),
+        }
+    }
+
+    Err(OAuthError::UnknownError(format!("Timed out waiting for lock {:?}", lock_path)))
+}
+
+impl<V> JsonFile<V> where V: Serialize + Deserialize {
+
+    pub fn new<P: AsRef<Path>>(path: P) -> JsonFile<V> {
+        JsonFile {
+            path: path.as_ref().to_path_buf(),
+            lock: Mutex::new(()),
+            _value: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&self) -> Result<HashMap<String, V>, OAuthError> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(HashMap::new());
+            },
+            Err(err) => return Err(OAuthError::from(err)),
+        };
+
+        let mut contents = String::new();
+        let _ = try!(file.read_to_string(&mut contents));
+        if contents.trim().is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let data = try!(serde_json::from_str(&contents));
+        Ok(data)
+    }
+
+    // Writes to a temporary file first so readers never see a partial map.
+    // Maps hold sessions and pending logins, so only the owner may read them.
+    fn save(&self, data: &HashMap<String, V>) -> Result<(), OAuthError> {
+        let json = try!(serde_json::to_string(data));
+        let tmp_path = self.path.with_extension("tmp");
+        let _ = fs::remove_file(&tmp_path);
+        {
+            let mut file = try!(OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp_path));
+            try!(file.write_all(json.as_bytes()));
+            try!(file.sync_all());
+        }
+        try!(fs::rename(&tmp_path, &self.path));
+        Ok(())
+    }
+
+    // Reads the map without modifying it
+    pub fn read<F, R>(&self, f: F) -> Result<R, OAuthError>
+        where F: FnOnce(&HashMap<String, V>) -> R
+    {
+        let _guard = self.lock.lock().unwrap();
+        let _lock = try!(acquire(&self.path));
+        let data = try!(self.load());
+        Ok(f(&data))
+    }
+
+    // Reads, modifies and writes back the map atomically
+    pub fn update<F, R>(&self, f: F) -> Result<R, OAuthError>
+        where F: FnOnce(&mut HashMap<String, V>) -> R
+    {
+        let _guard = self.lock.lock().unwrap();
+        let _lock = try!(acquire(&self.path));
+        let mut data = try!(self.load());
+        let result = f(&mut data);
+        try!(self.save(&data));
+        Ok(result)
+    }
+}
@@ -0,0 +1,79 @@
+extern crate example_application;
+extern crate rustc_serialize;
+
+use rustc_serialize::base64::{self, ToBase64, FromBase64};
+use example_application::oauth::crypto::aead::{self, SessionCodec, CHUNK_SIZE, MAX_CHUNKS};
+
+fn key() -> Vec<u8> {
+    aead::decode_key(&aead::generate_key().unwrap()).unwrap()
+}
+
+#[test]
+fn sealed_values_open_with_the_same_name() {
+    let codec = SessionCodec::new(vec![key()]).unwrap();
+    let value = codec.seal("session", b"payload").unwrap();
+
+    assert!(!value.contains("payload"));
+    assert_eq!(codec.open("session", &value), Some(b"payload".to_vec()));
+    assert_eq!(codec.open("other", &value), None);
+}
+
+#[test]
+fn tampered_values_do_not_open() {
+    let codec = SessionCodec::new(vec![key()]).unwrap();
+    let value = codec.seal("session", b"payload").unwrap();
+
+    let mut parts: Vec<String> = value.split('.').map(|part| part.to_string()).collect();
+    parts[2] = codec.seal("session", b"other").unwrap().split('.').nth(2).unwrap().to_string();
+
+    assert_eq!(codec.open("session", &parts.join(".")), None);
+    assert_eq!(codec.open("session", "not.a.sealed.value"), None);
+}
+
+#[test]
+fn truncated_tags_and_ivs_do_not_open() {
+    let codec = SessionCodec::new(vec![key()]).unwrap();
+    let value = codec.seal("session", b"payload").unwrap();
+    let parts: Vec<&str> = value.split('.').collect();
+
+    let tag = parts[3].from_base64().unwrap();
+    let truncated = [parts[0], parts[1], parts[2], &tag[..1].to_base64(base64::URL_SAFE)].join(".");
+    assert_eq!(codec.open("session", &truncated), None);
+
+    let iv = parts[1].from_base64().unwrap();
+    let short_iv = [parts[0], &iv[..8].to_base64(base64::URL_SAFE), parts[2], parts[3]].join(".");
+    assert_eq!(codec.open("session", &short_iv), None);
+}
+
+#[test]
+fn rotated_keys_still_open_old_values() {
+    let (old, new) = (key(), key());
+    let before = SessionCodec::new(vec![old.clone()]).unwrap();
+    let during = SessionCodec::new(vec![new.clone(), old]).unwrap();
+    let after = SessionCodec::new(vec![new]).unwrap();
+    let value = before.seal("session", b"payload").unwrap();
+
+    assert_eq!(during.open("session", &value), Some(b"payload".to_vec()));
+    assert!(!during.is_current(&value));
+    assert!(during.is_current(&during.seal("session", b"payload").unwrap()));
+    assert_eq!(after.open("session", &value), None);
+}
+
+#[test]
+fn codecs_need_256_bit_keys() {
+    assert!(SessionCodec::new(Vec::new()).is_err());
+    assert!(SessionCodec::new(vec![vec![0u8; 16]]).is_err());
+    assert!(aead::decode_key("c2hvcnQ").is_err());
+}
+
+#[test]
+fn large_values_are_split_up_to_the_limit() {
+    let value: String = ::std::iter::repeat('a').take(CHUNK_SIZE * 2 + 1).collect();
+    let chunks = aead::split(&value).unwrap();
+
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks.concat(), value);
+
+    let too_large: String = ::std::iter::repeat('a').take(CHUNK_SIZE * MAX_CHUNKS + 1).collect();
+    assert!(aead::split(&too_large).is_err());
+}
@@ -0,0 +1,195 @@
+extern crate example_application;
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use example_application::oauth::client::{self, ProviderClient};
+use example_application::oauth::settings::{self, Settings, Source};
+
+// Points the provider at a port which was just free, so calls to it are
+// refused
+fn settings() -> Settings {
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let json = format!(r#"{{
+        "public_key_path": "./priv/public-key.json",
+        "secret_key_path": "./priv/secret-key.json",
+        "client_id": "123123123",
+        "client_secret": "123123123",
+        "redirect_uri": "https://example.com/api/oauth",
+        "oauth_uri": "http://{}/v1",
+        "profile_uri": "http://{}/v1"
+    }}"#, addr, addr);
+    settings::load(&Source::from_json(&json).unwrap()).unwrap()
+}
+
+// Fails fast, waiting 50ms before the first of the retries
+fn provider_client(retries: u64) -> ProviderClient {
+    ProviderClient::new(Duration::from_secs(1), retries, Duration::from_millis(50)).unwrap()
+}
+
+// A provider answering every request with status and body. Returns its base
+// URL and the requests it received, headers and body as sent.
+fn provider_stub(status: u16, body: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = requests.clone();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let request = read_request(&mut stream);
+            received.lock().unwrap().push(request);
+            let _ = write!(stream, "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           status, body.len(), body);
+        }
+    });
+
+    (url, requests)
+}
+
+fn read_request(stream: &mut TcpStream) -> String {
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    let mut length = 0;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        if line.to_lowercase().starts_with("content-length:") {
+            length = line["content-length:".len()..].trim().parse().unwrap_or(0);
+        }
+        request.push_str(&line);
+        if line == "\r\n" {
+            break;
+        }
+    }
+
+    let mut body = vec![0u8; length];
+    let _ = reader.read_exact(&mut body);
+    request.push_str(&String::from_utf8_lossy(&body));
+    request
+}
+
+#[test]
+fn backoff_doubles_with_each_retry() {
+    let client = ProviderClient::new(Duration::from_secs(1), 3, Duration::from_millis(100)).unwrap();
+
+    assert_eq!(client.backoff(1), Duration::from_millis(100));
+    assert_eq!(client.backoff(2), Duration::from_millis(200));
+    assert_eq!(client.backoff(3), Duration::from_millis(400));
+}
+
+// An error caused by another, like a client error wrapping an I/O error
+#[derive(Debug)]
+struct Wrapped(io::Error);
+
+impl fmt::Display for Wrapped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "wrapped: {}", self.0)
+    }
+}
+
+impl Error for Wrapped {
+    fn source(&self) -> Option<&(Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[test]
+fn only_refused_connections_count_as_never_sent() {
+    assert!(client::never_sent(&Wrapped(io::Error::from(io::ErrorKind::ConnectionRefused))));
+    assert!(!client::never_sent(&Wrapped(io::Error::from(io::ErrorKind::TimedOut))));
+    assert!(!client::never_sent(&Wrapped(io::Error::from(io::ErrorKind::ConnectionReset))));
+    assert!(!client::never_sent(&fmt::Error));
+}
+
+#[test]
+fn unreachable_providers_are_reported() {
+    let client = provider_client(2);
+
+    let err = client.exchange_code(&settings(), "code".to_string(), None).err().unwrap();
+    assert_eq!(err.code(), "provider_unreachable");
+}
+
+#[test]
+fn every_call_converts_failures_the_same_way() {
+    let client = provider_client(0);
+    let settings = settings();
+
+    assert_eq!(client.fetch_profile(&settings, "token").err().unwrap().code(), "provider_unreachable");
+    assert_eq!(client.refresh(&settings, "refresh".to_string()).err().unwrap().code(), "provider_unreachable");
+    assert_eq!(client.revoke(&settings, "token".to_string(), "access_token").err().unwrap().code(), "provider_unreachable");
+}
+
+#[test]
+fn refresh_grants_are_encoded_like_revocations() {
+    let (url, requests) = provider_stub(200, r#"{"token_type": "bearer", "access_token": "new"}"#);
+    let client = provider_client(0);
+    let mut settings = settings();
+    settings.endpoints.token = format!("{}/v1/token", url);
+
+    client.refresh(&settings, "refresh".to_string()).unwrap();
+    settings.discovery_issuer = Some(url.clone());
+    client.refresh(&settings, "refresh".to_string()).unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert!(requests[0].contains("application/json"));
+    assert!(requests[0].contains(r#""grant_type":"refresh_token""#));
+    assert!(requests[1].contains("application/x-www-form-urlencoded"));
+    assert!(requests[1].contains("grant_type=refresh_token&refresh_token=refresh"));
+}
+
+#[test]
+fn code_exchanges_are_encoded_like_refresh_grants() {
+    let (url, requests) = provider_stub(200, r#"{"token_type": "bearer", "access_token": "new"}"#);
+    let client = provider_client(0);
+    let mut settings = settings();
+    settings.endpoints.token = format!("{}/v1/token", url);
+
+    client.exchange_code(&settings, "code".to_string(), None).unwrap();
+    settings.discovery_issuer = Some(url.clone());
+    client.exchange_code(&settings, "code".to_string(), Some("verifier".to_string())).unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert!(requests[0].contains("application/json"));
+    assert!(requests[0].contains(r#""grant_type":"authorization_code","code":"code","redirect_uri":"https://example.com/api/oauth""#));
+    assert!(requests[1].contains("application/x-www-form-urlencoded"));
+    assert!(requests[1].contains("grant_type=authorization_code&code=code&redirect_uri=https%3A%2F%2Fexample.com%2Fapi%2Foauth"));
+    assert!(requests[1].contains("code_verifier=verifier"));
+}
+
+#[test]
+fn token_requests_are_not_repeated_after_an_answer() {
+    let (url, requests) = provider_stub(503, r#"{"error": "temporarily_unavailable"}"#);
+    let client = provider_client(2);
+    let mut settings = settings();
+    settings.endpoints.token = format!("{}/v1/token", url);
+
+    let err = client.exchange_code(&settings, "code".to_string(), None).err().unwrap();
+    assert_eq!(err.code(), "temporarily_unavailable");
+    assert_eq!(requests.lock().unwrap().len(), 1);
+
+    assert!(client.refresh(&settings, "refresh".to_string()).is_err());
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn profile_requests_are_repeated_after_a_server_error() {
+    let (url, requests) = provider_stub(503, r#"{"error": "temporarily_unavailable"}"#);
+    let client = provider_client(2);
+    let mut settings = settings();
+    settings.endpoints.userinfo = format!("{}/v1/profile", url);
+
+    assert!(client.fetch_profile(&settings, "token").is_err());
+    assert_eq!(requests.lock().unwrap().len(), 3);
+}
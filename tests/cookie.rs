@@ -0,0 +1,65 @@
+extern crate example_application;
+
+use example_application::oauth::cookie::{CookiePolicy, SameSite};
+
+fn host_policy() -> CookiePolicy {
+    CookiePolicy {
+        domain: None,
+        path: "/".to_string(),
+        secure: true,
+        same_site: Some(SameSite::Strict),
+        max_age: Some(3600),
+        prefix: "__Host-".to_string(),
+    }
+}
+
+#[test]
+fn build_applies_every_attribute() {
+    let policy = CookiePolicy {
+        domain: Some("www.example.com".to_string()),
+        secure: true,
+        max_age: Some(600),
+        ..CookiePolicy::default()
+    };
+    let cookie = policy.build("state", "nonce".to_string()).to_string();
+
+    assert!(cookie.starts_with("state=nonce"));
+    assert!(cookie.contains("; HttpOnly"));
+    assert!(cookie.contains("; Secure"));
+    assert!(cookie.contains("; Path=/api; SameSite=Lax"));
+    assert!(cookie.contains("; Domain=www.example.com"));
+    assert!(cookie.contains("; Max-Age=600"));
+}
+
+#[test]
+fn names_are_prefixed() {
+    let cookie = host_policy().build("state", "nonce".to_string());
+
+    assert_eq!(cookie.name(), "__Host-state");
+}
+
+#[test]
+fn expire_keeps_path_and_domain() {
+    let policy = CookiePolicy {
+        domain: Some("www.example.com".to_string()),
+        ..CookiePolicy::default()
+    };
+    let cookie = policy.expire("state").to_string();
+
+    assert!(cookie.starts_with("state=;"));
+    assert!(cookie.contains("; Path=/api"));
+    assert!(cookie.contains("; Domain=www.example.com"));
+    assert!(cookie.contains("; Max-Age=0"));
+}
+
+#[test]
+fn host_prefix_rules_are_enforced() {
+    assert!(host_policy().problems().is_empty());
+
+    let mut policy = host_policy();
+    policy.secure = false;
+    policy.domain = Some("www.example.com".to_string());
+    policy.path = "/api".to_string();
+
+    assert_eq!(policy.problems().len(), 3);
+}
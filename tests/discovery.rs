@@ -0,0 +1,54 @@
+extern crate example_application;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::time::Duration;
+use example_application::oauth::client::ProviderClient;
+use example_application::oauth::discovery::{self, Endpoints};
+
+const ISSUER: &'static str = "https://accounts.example.com";
+
+const DOCUMENT: &'static str = r#"{
+    "issuer": "https://accounts.example.com",
+    "authorization_endpoint": "https://accounts.example.com/authorization",
+    "token_endpoint": "https://oauth.example.com/v1/token",
+    "userinfo_endpoint": "https://profile.example.com/v1/profile",
+    "revocation_endpoint": "https://oauth.example.com/v1/destroy",
+    "jwks_uri": "https://oauth.example.com/v1/jwks",
+    "scopes_supported": ["openid", "profile"]
+}"#;
+
+fn client() -> ProviderClient {
+    ProviderClient::new(Duration::from_secs(1), 0, Duration::from_millis(50)).unwrap()
+}
+
+#[test]
+fn endpoints_default_to_the_fxa_layout() {
+    let endpoints = Endpoints::from_uris("https://oauth.example.com/v1/", "https://profile.example.com/v1", ISSUER);
+
+    assert_eq!(endpoints.token, "https://oauth.example.com/v1/token");
+    assert_eq!(endpoints.userinfo, "https://profile.example.com/v1/profile");
+    assert_eq!(endpoints.jwks, "https://oauth.example.com/v1/jwks");
+    assert_eq!(endpoints.revocation, Some("https://oauth.example.com/v1/destroy".to_string()));
+}
+
+#[test]
+fn discover_uses_a_fresh_cached_document() {
+    let path = env::temp_dir().join("openid-configuration.json");
+    File::create(&path).unwrap().write_all(DOCUMENT.as_bytes()).unwrap();
+
+    let metadata = discovery::discover(&client(), ISSUER, Some(&path), 3600).unwrap();
+    let endpoints = Endpoints::from_metadata(&metadata, "https://profile.example.com/v1");
+
+    assert_eq!(endpoints.issuer, ISSUER);
+    assert_eq!(endpoints.authorization, "https://accounts.example.com/authorization");
+    assert_eq!(endpoints.token, "https://oauth.example.com/v1/token");
+    assert_eq!(endpoints.jwks, "https://oauth.example.com/v1/jwks");
+}
+
+#[test]
+fn configuration_uri_is_under_well_known() {
+    assert_eq!(discovery::configuration_uri("https://accounts.example.com/"),
+               "https://accounts.example.com/.well-known/openid-configuration");
+}
@@ -0,0 +1,89 @@
+extern crate example_application;
+extern crate rocket;
+extern crate serde_json;
+
+use std::error::Error;
+use std::io;
+use rocket::http::Status;
+use example_application::error::{OAuthError, ProviderError, ErrorFormat};
+
+#[test]
+fn provider_errors_map_to_their_own_variants() {
+    assert_eq!(ProviderError::from_code("access_denied"), ProviderError::AccessDenied);
+    assert_eq!(ProviderError::from_code("invalid_grant"), ProviderError::InvalidGrant);
+    assert_eq!(ProviderError::from_code("slow_down"), ProviderError::Other("slow_down".to_string()));
+    assert_eq!(ProviderError::from_code("slow_down").code(), "provider_error");
+    assert_eq!(ProviderError::from_code("slow_down").raw_code(), "slow_down");
+}
+
+#[test]
+fn provider_responses_are_parsed() {
+    match OAuthError::from_provider_response("400 Bad Request", r#"{"error": "invalid_grant", "error_description": "Code expired"}"#) {
+        OAuthError::ProviderError(ProviderError::InvalidGrant, Some(ref description)) => assert_eq!(description, "Code expired"),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // FxA describes errors in message
+    match OAuthError::from_provider_response("400 Bad Request", r#"{"code": 400, "error": "Bad Request", "message": "Unknown code"}"#) {
+        OAuthError::ProviderError(ProviderError::Other(_), Some(ref description)) => assert_eq!(description, "Unknown code"),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    match OAuthError::from_provider_response("502 Bad Gateway", "<html>proxy error</html>") {
+        OAuthError::UnknownError(ref message) => assert!(message.contains("502")),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn errors_have_stable_codes_and_statuses() {
+    let denied = OAuthError::ProviderError(ProviderError::AccessDenied, None);
+    assert_eq!(denied.code(), "access_denied");
+    assert_eq!(denied.status(), Status::Forbidden);
+
+    let io = OAuthError::from(io::Error::new(io::ErrorKind::Other, "disk on fire"));
+    assert_eq!(io.code(), "internal_error");
+    assert_eq!(io.status(), Status::InternalServerError);
+    assert!(io.cause().is_some());
+    assert_eq!(io.to_string(), "I/O error: disk on fire");
+
+    assert_eq!(OAuthError::HTTPError(Status::BadRequest).code(), "bad_request");
+    assert_eq!(OAuthError::JWTError("bad nonce".to_string()).status(), Status::Unauthorized);
+    assert_eq!(OAuthError::LoginExpired.code(), "login_expired");
+    assert_eq!(OAuthError::LoginExpired.status(), Status::BadRequest);
+}
+
+#[test]
+fn responses_do_not_leak_details() {
+    let error = OAuthError::UnknownError("token endpoint https://internal:8443 said no".to_string());
+
+    let json: serde_json::Value = serde_json::from_str(&error.respond_as(ErrorFormat::JSON).body()).unwrap();
+    assert_eq!(json["error"], "internal_error");
+    assert!(!json["error_description"].as_str().unwrap().contains("internal:8443"));
+
+    let error = OAuthError::UnknownError("<script>".to_string());
+    let html = error.respond_as(ErrorFormat::HTML).body();
+    assert!(html.contains("internal_error"));
+    assert!(!html.contains("<script>"));
+
+    // Unknown provider codes are logged but not passed on
+    let other = || OAuthError::ProviderError(ProviderError::Other("<b>internal:8443</b>".to_string()), Some("said no".to_string()));
+    assert!(other().to_string().contains("<b>internal:8443</b>"));
+
+    let json: serde_json::Value = serde_json::from_str(&other().respond_as(ErrorFormat::JSON).body()).unwrap();
+    assert_eq!(json["error"], "provider_error");
+    assert!(!json.to_string().contains("internal:8443"));
+    assert!(!json.to_string().contains("said no"));
+
+    let html = other().respond_as(ErrorFormat::HTML).body();
+    assert!(html.contains("provider_error"));
+    assert!(!html.contains("internal:8443"));
+
+#[test]
+fn accept_picks_the_format() {
+    assert_eq!(ErrorFormat::from_accept(None), ErrorFormat::JSON);
+    assert_eq!(ErrorFormat::from_accept(Some("*/*")), ErrorFormat::JSON);
+    assert_eq!(ErrorFormat::from_accept(Some("application/json")), ErrorFormat::JSON);
+    assert_eq!(ErrorFormat::from_accept(Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")), ErrorFormat::HTML);
+    assert_eq!(ErrorFormat::from_accept(Some("text/html;q=0.5, application/json")), ErrorFormat::JSON);
+}
@@ -0,0 +1,175 @@
+extern crate example_application;
+
+use std::env;
+use std::fs;
+use std::time::Duration;
+use example_application::error::{OAuthError, ProviderError};
+use example_application::oauth::OAuthRequest;
+use example_application::oauth::flow::{Flow, FlowCheck, FlowState, FileFlowStore};
+
+fn callback(state: &str) -> OAuthRequest {
+    OAuthRequest {
+        state: state.to_string(),
+        code: Some("code".to_string()),
+        error: None,
+        error_description: None,
+        error_uri: None,
+    }
+}
+
+fn cancelled(state: &str) -> OAuthRequest {
+    OAuthRequest {
+        code: None,
+        error: Some("access_denied".to_string()),
+        error_description: Some("User cancelled".to_string()),
+        ..callback(state)
+    }
+}
+
+fn file_flow(name: &str, ttl: Duration) -> Flow {
+    let path = env::temp_dir().join(name);
+    let _ = fs::remove_file(&path);
+    let store = FileFlowStore::new(path.to_str().unwrap());
+    Flow::with_store(Box::new(store), ttl)
+}
+
+#[test]
+fn verify_accepts_a_pending_state_once() {
+    let flow = Flow::new();
+    let nonce = flow.generate_persistent_nonce().unwrap();
+
+    assert!(flow.find(nonce.clone()).unwrap());
+    assert!(flow.verify(callback(&nonce), nonce.clone()).unwrap().is_some());
+}
+
+#[test]
+fn verify_rejects_replayed_state() {
+    let flow = Flow::new();
+    let nonce = flow.generate_persistent_nonce().unwrap();
+
+    assert!(flow.verify(callback(&nonce), nonce.clone()).unwrap().is_some());
+    assert!(!flow.verify(callback(&nonce), nonce.clone()).unwrap().is_some());
+    assert!(!flow.find(nonce).unwrap());
+}
+
+#[test]
+fn verify_rejects_and_consumes_expired_state() {
+    let flow = Flow::with_ttl(Duration::from_secs(0));
+    let nonce = flow.generate_persistent_nonce().unwrap();
+
+    assert!(!flow.find(nonce.clone()).unwrap());
+    assert!(!flow.verify(callback(&nonce), nonce.clone()).unwrap().is_some());
+    assert!(!flow.remove(nonce).unwrap());
+}
+
+#[test]
+fn verify_consumes_state_on_mismatched_cookie() {
+    let flow = Flow::new();
+    let nonce = flow.generate_persistent_nonce().unwrap();
+
+    assert!(!flow.verify(callback(&nonce), "other".to_string()).unwrap().is_some());
+    assert!(!flow.verify(callback(&nonce), nonce).unwrap().is_some());
+}
+
+#[test]
+fn check_tells_apart_why_a_callback_failed() {
+    let flow = Flow::new();
+    let nonce = flow.generate_persistent_nonce().unwrap();
+    match flow.check(&nonce, &nonce).unwrap() {
+        FlowCheck::Valid(_) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // Started here, finished in a browser without the state cookie
+    let nonce = flow.generate_persistent_nonce().unwrap();
+    match flow.check(&nonce, "").unwrap() {
+        FlowCheck::DifferentBrowser(_) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // Both consumed the state
+    match flow.check(&nonce, &nonce).unwrap() {
+        FlowCheck::Unknown => {},
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn finish_hands_out_the_state_of_valid_callbacks() {
+    assert!(FlowCheck::Valid(FlowState::new()).finish(&callback("state")).is_ok());
+}
+
+#[test]
+fn finish_reports_provider_errors_for_valid_callbacks_only() {
+    match FlowCheck::Valid(FlowState::new()).finish(&cancelled("state")) {
+        Err(OAuthError::ProviderError(ProviderError::AccessDenied, _)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn finish_reports_unknown_states_as_expired() {
+    match FlowCheck::Unknown.finish(&callback("state")) {
+        Err(OAuthError::LoginExpired) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    match FlowCheck::Unknown.finish(&cancelled("state")) {
+        Err(OAuthError::LoginExpired) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn finish_reports_other_browsers() {
+    match FlowCheck::DifferentBrowser(FlowState::new()).finish(&callback("state")) {
+        Err(OAuthError::DifferentBrowserError(_)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+    match FlowCheck::DifferentBrowser(FlowState::new()).finish(&cancelled("state")) {
+        Err(OAuthError::DifferentBrowserError(_)) => {},
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn check_treats_expired_state_as_unknown() {
+    let flow = Flow::with_ttl(Duration::from_secs(0));
+    let nonce = flow.generate_persistent_nonce().unwrap();
+
+    match flow.check(&nonce, &nonce).unwrap() {
+        FlowCheck::Unknown => {},
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn sweep_evicts_expired_states() {
+    let flow = Flow::with_ttl(Duration::from_secs(0));
+    flow.generate_persistent_nonce().unwrap();
+    flow.generate_persistent_nonce().unwrap();
+
+    // Generating a nonce sweeps previously expired ones first
+    assert_eq!(flow.sweep().unwrap(), 1);
+    assert_eq!(flow.sweep().unwrap(), 0);
+}
+
+#[test]
+fn file_store_is_shared_between_instances() {
+    let first = file_flow("flow-shared.json", Duration::from_secs(60));
+    let nonce = first.generate_persistent_nonce().unwrap();
+
+    let path = env::temp_dir().join("flow-shared.json");
+    let second = Flow::with_store(Box::new(FileFlowStore::new(path.to_str().unwrap())),
+                                  Duration::from_secs(60));
+
+    assert!(second.verify(callback(&nonce), nonce.clone()).unwrap().is_some());
+    assert!(!first.verify(callback(&nonce), nonce).unwrap().is_some());
+}
+
+#[test]
+fn file_store_rejects_expired_state() {
+    let flow = file_flow("flow-expired.json", Duration::from_secs(0));
+    let nonce = flow.generate_persistent_nonce().unwrap();
+
+    assert!(!flow.verify(callback(&nonce), nonce).unwrap().is_some());
+}
@@ -0,0 +1,67 @@
+extern crate example_application;
+extern crate rocket;
+extern crate serde_json;
+
+use std::time::Duration;
+use rocket::http::Status;
+use example_application::error::{OAuthError, ErrorFormat, LOGIN_PATH};
+use example_application::oauth::guard::{self, AuthenticatedUser, RequireScope, Profile, Email};
+use example_application::oauth::session::{Session, SessionData};
+
+fn user(scopes: &[&str]) -> AuthenticatedUser {
+    let scopes = scopes.iter().map(|scope| scope.to_string()).collect();
+    AuthenticatedUser::from_session(Session {
+        id: "id".to_string(),
+        data: SessionData::new(&Duration::from_secs(60), "uid".to_string(), "user@example.com".to_string(),
+                               "token".to_string(), "bearer".to_string(), scopes),
+    })
+}
+
+#[test]
+fn users_come_from_the_session() {
+    let user = user(&["profile"]);
+    assert_eq!(user.uid, "uid");
+    assert_eq!(user.email, "user@example.com");
+    assert_eq!(user.scopes, vec!["profile".to_string()]);
+}
+
+#[test]
+fn scopes_cover_their_sub_scopes() {
+    assert!(guard::scope_covers("profile", "profile"));
+    assert!(guard::scope_covers("profile", "profile:email"));
+    assert!(!guard::scope_covers("profile:email", "profile"));
+    assert!(!guard::scope_covers("profile", "profiles"));
+
+    assert!(user(&["openid", "profile"]).has_scope("profile:email"));
+    assert!(!user(&["profile:email"]).has_scope("profile:avatar"));
+}
+
+#[test]
+fn missing_scopes_are_refused() {
+    assert!(RequireScope::<Email>::new(user(&["profile:email"])).is_ok());
+
+    match RequireScope::<Profile>::new(user(&["profile:email"])) {
+        Err(OAuthError::MissingScope(ref scope)) => assert_eq!(scope, "profile"),
+        Err(other) => panic!("unexpected {:?}", other),
+        Ok(_) => panic!("profile:email does not cover profile"),
+    }
+}
+
+#[test]
+fn denials_tell_the_client_what_to_do() {
+    assert_eq!(OAuthError::LoginRequired.status(), Status::Unauthorized);
+    let json: serde_json::Value = serde_json::from_str(&OAuthError::LoginRequired.respond_as(ErrorFormat::JSON).body()).unwrap();
+    assert_eq!(json["error"], "login_required");
+    assert_eq!(json["login"], LOGIN_PATH);
+    assert!(json.get("scope").is_none());
+
+    let missing = OAuthError::MissingScope("profile".to_string());
+    assert_eq!(missing.status(), Status::Forbidden);
+    let json: serde_json::Value = serde_json::from_str(&missing.respond_as(ErrorFormat::JSON).body()).unwrap();
+    assert_eq!(json["error"], "insufficient_scope");
+    assert_eq!(json["scope"], "profile");
+    assert!(json.get("login").is_none());
+
+    let html = OAuthError::MissingScope("profile".to_string()).respond_as(ErrorFormat::HTML).body();
+    assert!(html.contains("<code>profile</code>"));
+}
@@ -0,0 +1,74 @@
+extern crate example_application;
+extern crate serde_json;
+
+use example_application::oauth::crypto::{self, KeySpec, SigningKey};
+use example_application::oauth::crypto::jwt;
+use example_application::oauth::id_token::{IdTokenClaims, IdTokenVerifier};
+
+const ISSUER: &'static str = "https://accounts.example.com";
+const CLIENT_ID: &'static str = "client";
+
+fn verifier() -> IdTokenVerifier {
+    IdTokenVerifier::new("https://oauth.example.com/jwks".to_string(),
+                         ISSUER.to_string(),
+                         CLIENT_ID.to_string())
+}
+
+fn claims() -> IdTokenClaims {
+    let now = jwt::timestamp();
+    IdTokenClaims {
+        iss: ISSUER.to_string(),
+        sub: "uid".to_string(),
+        aud: serde_json::Value::String(CLIENT_ID.to_string()),
+        exp: now + 300,
+        iat: now,
+        nonce: Some("nonce".to_string()),
+    }
+}
+
+#[test]
+fn accepts_signed_claims_for_this_client() {
+    let (secret_key, public_key) = crypto::generate(KeySpec::default()).unwrap();
+    let key = SigningKey { kid: public_key.kid().to_string(), secret_key: secret_key };
+
+    let token = jwt::encode(&claims(), &key).unwrap();
+    let decoded: IdTokenClaims = jwt::decode(&token, &public_key).unwrap();
+
+    assert!(verifier().check_claims(&decoded, Some("nonce")).is_ok());
+}
+
+#[test]
+fn accepts_an_audience_array_containing_the_client() {
+    let mut claims = claims();
+    claims.aud = serde_json::from_str(r#"["other", "client"]"#).unwrap();
+
+    assert!(verifier().check_claims(&claims, Some("nonce")).is_ok());
+}
+
+#[test]
+fn rejects_another_issuer_or_audience() {
+    let mut other_issuer = claims();
+    other_issuer.iss = "https://evil.example.com".to_string();
+    let mut other_audience = claims();
+    other_audience.aud = serde_json::Value::String("other".to_string());
+
+    assert!(verifier().check_claims(&other_issuer, Some("nonce")).is_err());
+    assert!(verifier().check_claims(&other_audience, Some("nonce")).is_err());
+}
+
+#[test]
+fn rejects_a_missing_or_different_nonce() {
+    let mut missing = claims();
+    missing.nonce = None;
+
+    assert!(verifier().check_claims(&claims(), Some("other")).is_err());
+    assert!(verifier().check_claims(&missing, Some("nonce")).is_err());
+}
+
+#[test]
+fn rejects_tokens_issued_in_the_future() {
+    let mut claims = claims();
+    claims.iat = jwt::timestamp() + 3600;
+
+    assert!(verifier().check_claims(&claims, Some("nonce")).is_err());
+}
@@ -0,0 +1,80 @@
+extern crate example_application;
+extern crate rustc_serialize;
+
+use rustc_serialize::base64::{self, ToBase64, FromBase64};
+use example_application::oauth::crypto::jwe::{self, KeyUnlock};
+use example_application::oauth::secret::{Secret, SecretBytes};
+
+const PLAINTEXT: &'static [u8] = b"{\"kty\":\"OKP\",\"crv\":\"Ed25519\",\"x\":\"x\",\"d\":\"d\"}";
+
+fn kek() -> KeyUnlock {
+    KeyUnlock::Kek(SecretBytes::new(vec![7u8; 32]))
+}
+
+fn passphrase(value: &str) -> KeyUnlock {
+    KeyUnlock::Passphrase(Secret::new(value.to_string()))
+}
+
+#[test]
+fn passphrase_round_trip() {
+    let unlock = passphrase("correct horse battery staple");
+    let token = jwe::encrypt(PLAINTEXT, &unlock).unwrap();
+
+    assert!(jwe::is_encrypted(&token));
+    assert_eq!(token.split('.').count(), 5);
+    assert_eq!(jwe::decrypt(&token, &unlock).unwrap(), PLAINTEXT);
+}
+
+#[test]
+fn kek_round_trip() {
+    let unlock = kek();
+    let token = jwe::encrypt(PLAINTEXT, &unlock).unwrap();
+
+    assert_eq!(jwe::decrypt(&token, &unlock).unwrap(), PLAINTEXT);
+}
+
+#[test]
+fn decrypt_rejects_wrong_passphrase() {
+    let token = jwe::encrypt(PLAINTEXT, &passphrase("right")).unwrap();
+
+    assert!(jwe::decrypt(&token, &passphrase("wrong")).is_err());
+    assert!(jwe::decrypt(&token, &kek()).is_err());
+}
+
+#[test]
+fn decrypt_rejects_truncated_tags() {
+    let token = jwe::encrypt(PLAINTEXT, &kek()).unwrap();
+    let mut parts: Vec<String> = token.split('.').map(|part| part.to_string()).collect();
+    let tag = parts[4].from_base64().unwrap();
+    parts[4] = tag[..1].to_base64(base64::URL_SAFE);
+
+    assert!(jwe::decrypt(&parts.join("."), &kek()).is_err());
+}
+
+#[test]
+fn unlocks_are_not_printed() {
+    assert_eq!(format!("{:?}", passphrase("hunter2")), "Passphrase(Secret([redacted]))");
+    assert_eq!(format!("{:?}", kek()), "Kek(SecretBytes([redacted]))");
+}
+
+#[test]
+fn plain_json_is_not_encrypted() {
+    assert!(!jwe::is_encrypted("  {\"kty\":\"RSA\"}"));
+}
+
+#[test]
+fn decrypt_rejects_unreasonable_iteration_counts() {
+    let unlock = passphrase("correct horse battery staple");
+    let token = jwe::encrypt(PLAINTEXT, &unlock).unwrap();
+    let rest: Vec<&str> = token.splitn(2, '.').collect();
+
+    for p2c in &[1u32, 4000000000] {
+        let header = format!(r#"{{"alg":"PBES2-HS256+A128KW","enc":"A256GCM","p2s":"c2FsdHNhbHRzYWx0c2FsdA","p2c":{}}}"#, p2c);
+        let crafted = format!("{}.{}", header.as_bytes().to_base64(base64::URL_SAFE), rest[1]);
+
+        match jwe::decrypt(&crafted, &unlock) {
+            Err(err) => assert!(err.to_string().contains("p2c")),
+            Ok(_) => panic!("p2c {} accepted", p2c),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+extern crate example_application;
+extern crate reqwest;
+
+use std::time::{Duration, Instant};
+use reqwest::header::{Headers, CacheControl, CacheDirective};
+use example_application::oauth::crypto::jwks::{self, MIN_REFRESH_INTERVAL};
+
+fn headers(directives: Vec<CacheDirective>) -> Headers {
+    let mut headers = Headers::new();
+    headers.set(CacheControl(directives));
+    headers
+}
+
+#[test]
+fn cache_control_sets_the_ttl() {
+    assert_eq!(jwks::cache_ttl(&Headers::new()), Duration::from_secs(3600));
+    assert_eq!(jwks::cache_ttl(&headers(vec![CacheDirective::Public, CacheDirective::MaxAge(600)])), Duration::from_secs(600));
+    assert_eq!(jwks::cache_ttl(&headers(vec![CacheDirective::MaxAge(600), CacheDirective::NoCache])), Duration::from_secs(0));
+    assert_eq!(jwks::cache_ttl(&headers(vec![CacheDirective::NoStore])), Duration::from_secs(0));
+}
+
+#[test]
+fn expired_sets_are_refreshed() {
+    let fetched_at = Instant::now();
+    let expires_at = fetched_at + Duration::from_secs(600);
+
+    assert!(!jwks::should_refresh(fetched_at + Duration::from_secs(599), fetched_at, expires_at, true));
+    assert!(jwks::should_refresh(expires_at, fetched_at, expires_at, true));
+}
+
+#[test]
+fn unknown_kids_refresh_at_most_once_a_minute() {
+    let fetched_at = Instant::now();
+    let expires_at = fetched_at + Duration::from_secs(3600);
+    let interval = Duration::from_secs(MIN_REFRESH_INTERVAL);
+
+    assert!(!jwks::should_refresh(fetched_at + interval - Duration::from_secs(1), fetched_at, expires_at, false));
+    assert!(jwks::should_refresh(fetched_at + interval, fetched_at, expires_at, false));
+}
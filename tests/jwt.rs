@@ -0,0 +1,73 @@
+extern crate example_application;
+#[macro_use] extern crate serde_derive;
+
+use example_application::oauth::crypto::{self, KeySpec, PublicKey, SigningKey};
+use example_application::oauth::crypto::jwt;
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: u64,
+}
+
+fn claims(exp: u64) -> Claims {
+    Claims {
+        sub: "uid".to_string(),
+        exp: exp,
+    }
+}
+
+fn keypair(spec: KeySpec) -> (SigningKey, PublicKey) {
+    let (secret_key, public_key) = crypto::generate(spec).unwrap();
+    let key = SigningKey { kid: public_key.kid().to_string(), secret_key: secret_key };
+    (key, public_key)
+}
+
+#[test]
+fn encode_and_decode_round_trip() {
+    let specs = [(KeySpec::RSA(2048), "RS256"), (KeySpec::P256, "ES256"), (KeySpec::Ed25519, "EdDSA")];
+
+    for &(spec, alg) in specs.iter() {
+        let (key, public_key) = keypair(spec);
+
+        let token = jwt::encode(&claims(jwt::timestamp() + 60), &key).unwrap();
+        let header = jwt::decode_header(&token).unwrap();
+        let decoded: Claims = jwt::decode(&token, &public_key).unwrap();
+
+        assert_eq!(header.alg, alg);
+        assert_eq!(header.kid, Some(public_key.kid().to_string()));
+        assert_eq!(decoded.sub, "uid");
+    }
+}
+
+#[test]
+fn decode_rejects_expired_tokens() {
+    let (key, public_key) = keypair(KeySpec::default());
+
+    let token = jwt::encode(&claims(jwt::timestamp() - 3600), &key).unwrap();
+    let decoded: Result<Claims, _> = jwt::decode(&token, &public_key);
+
+    assert!(decoded.is_err());
+}
+
+#[test]
+fn decode_rejects_other_keys() {
+    let (key, _) = keypair(KeySpec::P256);
+    let (_, other_public_key) = keypair(KeySpec::P256);
+
+    let token = jwt::encode(&claims(jwt::timestamp() + 60), &key).unwrap();
+    let decoded: Result<Claims, _> = jwt::decode(&token, &other_public_key);
+
+    assert!(decoded.is_err());
+}
+
+#[test]
+fn decode_rejects_algorithm_mismatch() {
+    let (key, _) = keypair(KeySpec::Ed25519);
+    let (_, rsa_public_key) = keypair(KeySpec::default());
+
+    let token = jwt::encode(&claims(jwt::timestamp() + 60), &key).unwrap();
+    let decoded: Result<Claims, _> = jwt::decode(&token, &rsa_public_key);
+
+    assert!(decoded.is_err());
+}
@@ -0,0 +1,72 @@
+extern crate example_application;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use example_application::oauth::crypto::{self, KeySpec, PublicKey};
+use example_application::oauth::crypto::keystore::{self, KeyStatus, KeyStore};
+
+fn keystore_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn init_publishes_next_key_without_signing_with_it() {
+    let dir = keystore_dir("keystore-init");
+    let manifest = keystore::init(&dir, KeySpec::default(), None).unwrap();
+    let store = KeyStore::open(&dir, 60, None).unwrap();
+
+    let current = manifest.keys.iter().find(|key| key.status == KeyStatus::Current).unwrap();
+    assert_eq!(store.published().len(), 2);
+    assert_eq!(store.signing_key().kid, current.kid);
+}
+
+#[test]
+fn rotate_waits_for_grace_period_before_promoting() {
+    let dir = keystore_dir("keystore-wait");
+    let before = keystore::init(&dir, KeySpec::default(), None).unwrap();
+    let after = keystore::rotate(&dir, 3600, KeySpec::default(), None).unwrap();
+
+    assert_eq!(before.keys.len(), after.keys.len());
+    for (old, new) in before.keys.iter().zip(after.keys.iter()) {
+        assert_eq!(old.status, new.status);
+    }
+}
+
+#[test]
+fn rotate_promotes_next_and_retires_current() {
+    let dir = keystore_dir("keystore-rotate");
+    let before = keystore::init(&dir, KeySpec::default(), None).unwrap();
+    let after = keystore::rotate(&dir, 0, KeySpec::P256, None).unwrap();
+
+    let old_current = before.keys.iter().find(|key| key.status == KeyStatus::Current).unwrap();
+    let old_next = before.keys.iter().find(|key| key.status == KeyStatus::Next).unwrap();
+
+    let status = |kid: &str| after.keys.iter().find(|key| key.kid == kid).map(|key| key.status);
+    assert_eq!(status(&old_next.kid), Some(KeyStatus::Current));
+    assert_eq!(status(&old_current.kid), Some(KeyStatus::Retiring));
+    assert!(after.keys.iter().any(|key| key.status == KeyStatus::Next));
+}
+
+#[test]
+fn thumbprint_matches_rfc_7638_example() {
+    let public_key = PublicKey::RSA {
+        kid: "2011-04-29".to_string(),
+        n: "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw".to_string(),
+        e: "AQAB".to_string(),
+    };
+
+    assert_eq!(public_key.thumbprint().unwrap(), "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+}
+
+#[test]
+fn generated_keys_are_named_by_thumbprint() {
+    for spec in &[KeySpec::RSA(2048), KeySpec::P256, KeySpec::Ed25519] {
+        let (secret_key, public_key) = crypto::generate(*spec).unwrap();
+
+        assert_eq!(public_key.kid(), public_key.thumbprint().unwrap());
+        assert_eq!(public_key.kid(), secret_key.thumbprint().unwrap());
+    }
+}
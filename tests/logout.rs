@@ -0,0 +1,82 @@
+extern crate example_application;
+extern crate serde_json;
+
+use std::time::Duration;
+use example_application::oauth::RevocationRequest;
+use example_application::oauth::session::{CsrfCheck, Session, SessionData};
+use example_application::oauth::settings::{self, Settings, Source};
+
+fn settings(extra: &str) -> Settings {
+    let json = format!(r#"{{
+        "public_key_path": "./priv/public-key.json",
+        "secret_key_path": "./priv/secret-key.json",
+        "client_id": "123123123",
+        "client_secret": "123123123",
+        "redirect_uri": "https://example.com/api/oauth",
+        {}
+        "logout_redirect_allowlist": ["/", "/goodbye", "https://other.example.com/"]
+    }}"#, extra);
+    settings::load(&Source::from_json(&json).unwrap()).unwrap()
+}
+
+fn session() -> Session {
+    Session {
+        id: "id".to_string(),
+        data: SessionData::new(&Duration::from_secs(60), "uid".to_string(), "user@example.com".to_string(),
+                               "token".to_string(), "bearer".to_string(), Vec::new()),
+    }
+}
+
+#[test]
+fn csrf_token_may_come_from_the_header_or_the_form() {
+    let settings = settings("");
+    let session = session();
+    let token = session.data.csrf_token.clone();
+
+    assert!(CsrfCheck::new(None, Some(token.clone())).verify(&settings, &session, None));
+    assert!(CsrfCheck::new(None, None).verify(&settings, &session, Some(&token)));
+    assert!(!CsrfCheck::new(None, None).verify(&settings, &session, None));
+    assert!(!CsrfCheck::new(None, Some("forged".to_string())).verify(&settings, &session, None));
+}
+
+#[test]
+fn csrf_check_refuses_other_origins() {
+    let settings = settings("");
+    let session = session();
+    let token = session.data.csrf_token.clone();
+
+    assert!(CsrfCheck::new(Some("https://example.com".to_string()), None).verify(&settings, &session, Some(&token)));
+    assert!(!CsrfCheck::new(Some("https://evil.example.com".to_string()), None).verify(&settings, &session, Some(&token)));
+    assert!(!CsrfCheck::new(Some("null".to_string()), None).verify(&settings, &session, Some(&token)));
+}
+
+#[test]
+fn sessions_without_a_csrf_token_cannot_log_out_by_post() {
+    let settings = settings("");
+    let mut session = session();
+    session.data.csrf_token = String::new();
+
+    assert!(!CsrfCheck::new(None, Some(String::new())).verify(&settings, &session, None));
+}
+
+#[test]
+fn logout_redirects_are_restricted_to_the_allowlist() {
+    let settings = settings("");
+
+    assert!(settings.allows_logout_redirect("/goodbye?from=menu"));
+    assert!(settings.allows_logout_redirect("https://other.example.com/"));
+    assert!(!settings.allows_logout_redirect("https://other.example.com/phish"));
+    assert!(!settings.allows_logout_redirect("//evil.example.com/"));
+    assert!(!settings.allows_logout_redirect("/elsewhere"));
+}
+
+#[test]
+fn public_clients_revoke_without_a_secret() {
+    let confidential = serde_json::to_value(&RevocationRequest::new(&settings(""), "token".to_string(), "access_token")).unwrap();
+    let public = serde_json::to_value(&RevocationRequest::new(&settings(r#""public_client": "true","#), "token".to_string(), "refresh_token")).unwrap();
+
+    assert_eq!(confidential["token_type_hint"], "access_token");
+    assert_eq!(confidential["client_secret"], "123123123");
+    assert_eq!(public["token_type_hint"], "refresh_token");
+    assert!(public.get("client_secret").is_none());
+}
@@ -0,0 +1,52 @@
+extern crate example_application;
+extern crate serde_json;
+
+use example_application::oauth::TokenRequest;
+use example_application::oauth::pkce;
+use example_application::oauth::settings::{self, Settings, Source};
+
+fn settings(extra: &str) -> Settings {
+    let json = format!(r#"{{
+        "public_key_path": "./priv/public-key.json",
+        "secret_key_path": "./priv/secret-key.json",
+        "client_id": "123123123",
+        "client_secret": "123123123",
+        "redirect_uri": "https://example.com/api/oauth",
+        {}
+    }}"#, extra);
+    settings::load(&Source::from_json(&json).unwrap()).unwrap()
+}
+
+#[test]
+fn code_challenge_matches_rfc_7636_appendix_b() {
+    assert_eq!(pkce::code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk").unwrap(),
+               "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+}
+
+#[test]
+fn generated_verifiers_are_unreserved_characters_of_allowed_length() {
+    let generated = pkce::generate().unwrap();
+
+    // RFC 7636 section 4.1: 43 to 128 of [A-Z] / [a-z] / [0-9] / "-" / "." / "_" / "~"
+    assert!(generated.code_verifier.len() >= 43 && generated.code_verifier.len() <= 128);
+    assert!(generated.code_verifier.chars().all(|c| {
+        (c >= 'A' && c <= 'Z') || (c >= 'a' && c <= 'z') || (c >= '0' && c <= '9') || "-._~".contains(c)
+    }));
+    assert_eq!(generated.code_challenge, pkce::code_challenge(&generated.code_verifier).unwrap());
+    assert!(pkce::generate().unwrap().code_verifier != generated.code_verifier);
+}
+
+#[test]
+fn public_clients_prove_the_verifier_instead_of_a_secret() {
+    let settings = settings(r#""public_client": "true""#);
+    let request = TokenRequest::new(&settings, "code".to_string(), Some("verifier".to_string()));
+    let json = serde_json::to_value(&request).unwrap();
+
+    assert_eq!(json["code_verifier"], "verifier");
+    assert!(json.get("client_secret").is_none());
+
+    let confidential = TokenRequest::new(&settings(r#""public_client": "false""#), "code".to_string(), None);
+    let json = serde_json::to_value(&confidential).unwrap();
+    assert_eq!(json["client_secret"], "123123123");
+    assert!(json.get("code_verifier").is_none());
+}
@@ -0,0 +1,66 @@
+extern crate example_application;
+extern crate serde_json;
+
+use std::time::Duration;
+use example_application::oauth::AccessResponse;
+use example_application::oauth::session::{ServerSessions, MemorySessionStore, SessionData};
+
+const PROFILE: &'static str = r#"{
+    "uid": "uid",
+    "email": "user@example.com",
+    "locale": "en-US,en;q=0.5",
+    "displayName": "User",
+    "avatar": "https://example.com/a/uid",
+    "avatarDefault": false,
+    "amrValues": ["pwd", "otp"],
+    "twoFactorAuthentication": true
+}"#;
+
+#[test]
+fn full_profiles_are_parsed() {
+    let profile: AccessResponse = serde_json::from_str(PROFILE).unwrap();
+
+    assert_eq!(profile.display_name, Some("User".to_string()));
+    assert_eq!(profile.avatar_default, Some(false));
+    assert_eq!(profile.amr_values, vec!["pwd".to_string(), "otp".to_string()]);
+    assert_eq!(profile.two_factor_authentication, Some(true));
+
+    // Served back with the provider's names
+    let json = serde_json::to_value(&profile).unwrap();
+    assert_eq!(json["displayName"], "User");
+    assert_eq!(json["twoFactorAuthentication"], true);
+}
+
+#[test]
+fn profiles_may_only_have_uid_and_email() {
+    let profile: AccessResponse = serde_json::from_str(r#"{"uid": "uid", "email": "user@example.com"}"#).unwrap();
+
+    assert!(profile.display_name.is_none());
+    assert!(profile.amr_values.is_empty());
+}
+
+#[test]
+fn sessions_keep_the_profile() {
+    let sessions = ServerSessions::new(Box::new(MemorySessionStore::new()), b"secret".to_vec());
+    let data = SessionData::new(&Duration::from_secs(60), "uid".to_string(), "user@example.com".to_string(),
+                                "token".to_string(), "bearer".to_string(), Vec::new())
+        .set_profile(Some(serde_json::from_str(PROFILE).unwrap()));
+    let (_, value) = sessions.create(data).unwrap();
+
+    let loaded = sessions.load(&value).unwrap().unwrap();
+    assert_eq!(loaded.data.profile.unwrap().locale, Some("en-US,en;q=0.5".to_string()));
+}
+
+#[test]
+fn sessions_from_before_profiles_still_load() {
+    let data: SessionData = serde_json::from_str(r#"{
+        "created_at": 0,
+        "expires_at": 0,
+        "uid": "uid",
+        "email": "user@example.com",
+        "access_token": "token",
+        "token_type": "bearer"
+    }"#).unwrap();
+
+    assert!(data.profile.is_none());
+}
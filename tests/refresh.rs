@@ -0,0 +1,102 @@
+extern crate example_application;
+extern crate rocket;
+extern crate serde_json;
+
+use std::net::TcpListener;
+use std::time::Duration;
+use rocket::http::CookieJar;
+use example_application::oauth::TokenResponse;
+use example_application::oauth::client::ProviderClient;
+use example_application::oauth::session::{Sessions, ServerSessions, MemorySessionStore, SessionData, REFRESH_LEEWAY};
+use example_application::oauth::settings::{self, Settings, Source};
+
+// Points the token endpoint at a port which was just free, so refreshes
+// are refused
+fn settings() -> Settings {
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let json = format!(r#"{{
+        "public_key_path": "./priv/public-key.json",
+        "secret_key_path": "./priv/secret-key.json",
+        "client_id": "123123123",
+        "client_secret": "123123123",
+        "redirect_uri": "https://example.com/api/oauth",
+        "oauth_uri": "http://{}/v1"
+    }}"#, addr);
+    settings::load(&Source::from_json(&json).unwrap()).unwrap()
+}
+
+fn sessions() -> Sessions {
+    let backend = ServerSessions::new(Box::new(MemorySessionStore::new()), b"secret".to_vec());
+    Sessions::new(Box::new(backend), Duration::from_secs(60))
+}
+
+fn client() -> ProviderClient {
+    ProviderClient::new(Duration::from_secs(1), 0, Duration::from_millis(0)).unwrap()
+}
+
+fn data() -> SessionData {
+    SessionData::new(&Duration::from_secs(60), "uid".to_string(), "user@example.com".to_string(),
+                     "token".to_string(), "bearer".to_string(), vec!["profile".to_string()])
+}
+
+#[test]
+fn token_responses_keep_refresh_tokens_and_expiry() {
+    let token_rsp: TokenResponse = serde_json::from_str(r#"{
+        "scopes": ["profile"],
+        "token_type": "bearer",
+        "access_token": "token",
+        "refresh_token": "refresh",
+        "expires_in": 3600
+    }"#).unwrap();
+    assert_eq!(token_rsp.refresh_token, Some("refresh".to_string()));
+    assert_eq!(token_rsp.expires_in, Some(3600));
+
+    let token_rsp: TokenResponse = serde_json::from_str(r#"{"token_type": "bearer", "access_token": "token"}"#).unwrap();
+    assert!(token_rsp.refresh_token.is_none());
+    assert!(token_rsp.scopes.is_empty());
+}
+
+#[test]
+fn tokens_are_refreshed_shortly_before_expiry() {
+    assert!(!data().needs_refresh(REFRESH_LEEWAY));
+    assert!(!data().set_expires_in(Some(3600)).needs_refresh(REFRESH_LEEWAY));
+    assert!(data().set_expires_in(Some(REFRESH_LEEWAY - 1)).needs_refresh(REFRESH_LEEWAY));
+}
+
+#[test]
+fn renewing_keeps_the_refresh_token_unless_rotated() {
+    let mut data = data().set_refresh_token(Some("refresh".to_string()));
+    data.renew(serde_json::from_str(r#"{"token_type": "bearer", "access_token": "new", "expires_in": 3600}"#).unwrap());
+
+    assert_eq!(data.access_token, "new");
+    assert_eq!(data.refresh_token, Some("refresh".to_string()));
+    assert_eq!(data.scopes, vec!["profile".to_string()]);
+    assert!(!data.needs_refresh(REFRESH_LEEWAY));
+
+    data.renew(serde_json::from_str(r#"{"token_type": "bearer", "access_token": "newer", "refresh_token": "rotated"}"#).unwrap());
+    assert_eq!(data.refresh_token, Some("rotated".to_string()));
+}
+
+#[test]
+fn fresh_sessions_are_left_alone() {
+    let (settings, sessions, jar) = (settings(), sessions(), CookieJar::new(b""));
+    let session = sessions.start(&settings, &jar, data().set_expires_in(Some(3600))).unwrap();
+
+    assert_eq!(sessions.current_refreshed(&settings, &client(), &jar).unwrap().unwrap().id, session.id);
+}
+
+#[test]
+fn sessions_end_when_the_token_cannot_be_refreshed() {
+    let (settings, sessions, jar) = (settings(), sessions(), CookieJar::new(b""));
+
+    // No refresh token
+    let _ = sessions.start(&settings, &jar, data().set_expires_in(Some(0))).unwrap();
+    assert!(sessions.current_refreshed(&settings, &client(), &jar).unwrap().is_none());
+    assert!(sessions.current(&settings, &jar).unwrap().is_none());
+
+    // The provider refuses or cannot be reached
+    let expiring = data().set_expires_in(Some(0)).set_refresh_token(Some("refresh".to_string()));
+    let _ = sessions.start(&settings, &jar, expiring).unwrap();
+    assert!(sessions.current_refreshed(&settings, &client(), &jar).unwrap().is_none());
+    assert!(sessions.current(&settings, &jar).unwrap().is_none());
+}
@@ -0,0 +1,24 @@
+extern crate example_application;
+
+use example_application::oauth::reload::Reloadable;
+
+#[test]
+fn clones_observe_a_replaced_value() {
+    let handle = Reloadable::new("old".to_string());
+    let managed = handle.clone();
+
+    handle.replace("new".to_string());
+
+    assert_eq!(*managed.current(), "new");
+}
+
+#[test]
+fn snapshots_are_unaffected_by_a_reload() {
+    let handle = Reloadable::new(1);
+    let snapshot = handle.current();
+
+    handle.replace(2);
+
+    assert_eq!(*snapshot, 1);
+    assert_eq!(*handle.current(), 2);
+}
@@ -0,0 +1,59 @@
+extern crate example_application;
+extern crate rocket;
+
+use rocket::http::Status;
+use example_application::error::OAuthError;
+use example_application::oauth::{OAuthResponse, FlowParams};
+use example_application::oauth::flow::Flow;
+use example_application::oauth::settings::{self, Settings, Source};
+
+fn settings() -> Settings {
+    let json = r#"{
+        "public_key_path": "./priv/public-key.json",
+        "secret_key_path": "./priv/secret-key.json",
+        "client_id": "123123123",
+        "client_secret": "123123123",
+        "redirect_uri": "https://example.com/api/oauth",
+        "return_to_allowlist": ["/", "/iframe"]
+    }"#;
+    settings::load(&Source::from_json(json).unwrap()).unwrap()
+}
+
+fn params(return_to: &str) -> FlowParams {
+    FlowParams {
+        return_to: Some(return_to.to_string()),
+        ..FlowParams::default()
+    }
+}
+
+#[test]
+fn other_sites_are_refused() {
+    let settings = settings();
+
+    assert!(!settings.allows_return_to("//evil.com"));
+    assert!(!settings.allows_return_to("/\\evil.com"));
+    assert!(!settings.allows_return_to("https://evil.com"));
+    assert!(!settings.allows_return_to("https://example.com/iframe"));
+}
+
+#[test]
+fn only_allowlisted_paths_are_accepted() {
+    let settings = settings();
+
+    assert!(settings.allows_return_to("/iframe"));
+    assert!(settings.allows_return_to("/iframe?x=1#y"));
+    assert!(!settings.allows_return_to("/elsewhere"));
+    assert!(!settings.allows_return_to("/iframe/deeper"));
+}
+
+#[test]
+fn logins_with_a_disallowed_return_to_are_bad_requests() {
+    let (settings, flow) = (settings(), Flow::new());
+
+    assert!(OAuthResponse::new(&settings, &flow, params("/iframe?x=1#y"), None).is_ok());
+    match OAuthResponse::new(&settings, &flow, params("//evil.com"), None) {
+        Err(OAuthError::HTTPError(status)) => assert_eq!(status, Status::BadRequest),
+        Err(other) => panic!("unexpected {:?}", other),
+        Ok(_) => panic!("//evil.com accepted"),
+    }
+}
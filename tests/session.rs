@@ -0,0 +1,165 @@
+extern crate example_application;
+extern crate rocket;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::iter;
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+use rocket::http::CookieJar;
+use example_application::oauth::crypto::aead::{self, SessionCodec};
+use example_application::oauth::session::{ServerSessions, CookieSessions, SessionBackend, Session, SessionData,
+                                          SessionStore, MemorySessionStore, FileSessionStore};
+use example_application::oauth::settings::{self, Settings, Source};
+
+fn data(ttl: u64) -> SessionData {
+    SessionData::new(&Duration::from_secs(ttl), "uid".to_string(), "user@example.com".to_string(),
+                     "token".to_string(), "bearer".to_string(), vec!["profile".to_string()])
+}
+
+fn memory_sessions() -> ServerSessions {
+    ServerSessions::new(Box::new(MemorySessionStore::new()), b"secret".to_vec())
+}
+
+#[test]
+fn signed_ids_round_trip() {
+    let sessions = memory_sessions();
+    let value = sessions.sign("abc").unwrap();
+
+    assert!(value.starts_with("abc."));
+    assert_eq!(sessions.unsign(&value).unwrap(), Some("abc".to_string()));
+}
+
+#[test]
+fn tampered_or_foreign_values_are_rejected() {
+    let sessions = memory_sessions();
+    let value = sessions.sign("abc").unwrap();
+    let other = ServerSessions::new(Box::new(MemorySessionStore::new()), b"other".to_vec());
+
+    assert_eq!(sessions.unsign(&value.replace("abc", "abd")).unwrap(), None);
+    assert_eq!(sessions.unsign("abc").unwrap(), None);
+    assert_eq!(other.unsign(&value).unwrap(), None);
+}
+
+#[test]
+fn created_sessions_load_until_destroyed() {
+    let sessions = memory_sessions();
+    let (session, value) = sessions.create(data(60)).unwrap();
+
+    let loaded = sessions.load(&value).unwrap().unwrap();
+    assert_eq!(loaded.id, session.id);
+    assert_eq!(loaded.data.email, "user@example.com");
+
+    sessions.destroy(&session).unwrap();
+    assert!(sessions.load(&value).unwrap().is_none());
+}
+
+#[test]
+fn expired_sessions_do_not_load() {
+    let sessions = memory_sessions();
+    let (_, value) = sessions.create(data(0)).unwrap();
+
+    assert!(sessions.load(&value).unwrap().is_none());
+}
+
+#[test]
+fn file_store_is_shared_between_instances() {
+    let path = env::temp_dir().join("example_application_sessions.json");
+    let _ = fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+
+    let first = ServerSessions::new(Box::new(FileSessionStore::new(path, b"secret").unwrap()), b"secret".to_vec());
+    let second = ServerSessions::new(Box::new(FileSessionStore::new(path, b"secret").unwrap()), b"secret".to_vec());
+    let (session, value) = first.create(data(60)).unwrap();
+
+    assert_eq!(second.load(&value).unwrap().unwrap().id, session.id);
+
+    second.destroy(&session).unwrap();
+    assert!(first.load(&value).unwrap().is_none());
+}
+
+#[test]
+fn file_store_records_are_sealed_and_private() {
+    let path = env::temp_dir().join("example_application_sealed_sessions.json");
+    let _ = fs::remove_file(&path);
+
+    let store = FileSessionStore::new(path.to_str().unwrap(), b"secret").unwrap();
+    store.insert("id".to_string(), data(60)).unwrap();
+    let mut contents = String::new();
+    File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+
+    assert!(!contents.contains("user@example.com"));
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(store.find("id").unwrap().unwrap().email, "user@example.com");
+
+    let other = FileSessionStore::new(path.to_str().unwrap(), b"other").unwrap();
+    assert!(other.find("id").unwrap().is_none());
+    assert_eq!(other.sweep().unwrap(), 1);
+}
+
+fn cookie_settings(keys: &[&str]) -> Settings {
+    let json = format!(r#"{{
+        "public_key_path": "./priv/public-key.json",
+        "secret_key_path": "./priv/secret-key.json",
+        "client_id": "123123123",
+        "client_secret": "123123123",
+        "redirect_uri": "https://example.com/",
+        "session_backend": "cookie",
+        "session_keys": "{}"
+    }}"#, keys.join(","));
+    settings::load(&Source::from_json(&json).unwrap()).unwrap()
+}
+
+#[test]
+fn cookie_sessions_live_in_sealed_cookies() {
+    let key = aead::generate_key().unwrap();
+    let settings = cookie_settings(&[&key]);
+    let sessions = CookieSessions::from_settings(&settings).unwrap();
+    let jar = CookieJar::new(b"");
+
+    let session = sessions.start(&settings, &jar, data(60)).unwrap();
+    let sealed = jar.find("session.0").unwrap().value().to_string();
+    assert!(!sealed.contains("user@example.com"));
+
+    assert_eq!(sessions.current(&settings, &jar).unwrap().unwrap().id, session.id);
+
+    sessions.end(&settings, &jar, &session).unwrap();
+    assert!(sessions.current(&settings, &jar).unwrap().is_none());
+}
+
+#[test]
+fn large_cookie_sessions_are_chunked() {
+    let key = aead::generate_key().unwrap();
+    let settings = cookie_settings(&[&key]);
+    let sessions = CookieSessions::from_settings(&settings).unwrap();
+    let jar = CookieJar::new(b"");
+
+    let mut large = data(60);
+    large.access_token = iter::repeat('t').take(6000).collect();
+    let session = sessions.start(&settings, &jar, large).unwrap();
+
+    assert!(jar.find("session.1").is_some());
+    assert_eq!(sessions.current(&settings, &jar).unwrap().unwrap().data.access_token.len(), 6000);
+
+    // Shrinking the session drops the chunks it no longer needs
+    let small = Session { id: session.id.clone(), data: data(60) };
+    sessions.save(&settings, &jar, &small).unwrap();
+    assert_eq!(jar.find("session.1").unwrap().value(), "");
+    assert_eq!(sessions.current(&settings, &jar).unwrap().unwrap().data.access_token, "token");
+}
+
+#[test]
+fn cookie_sessions_move_to_the_newest_key() {
+    let (old, new) = (aead::generate_key().unwrap(), aead::generate_key().unwrap());
+    let before = cookie_settings(&[&old]);
+    let during = cookie_settings(&[&new, &old]);
+    let jar = CookieJar::new(b"");
+
+    let _ = CookieSessions::from_settings(&before).unwrap().start(&before, &jar, data(60)).unwrap();
+    let sessions = CookieSessions::from_settings(&during).unwrap();
+    assert!(sessions.current(&during, &jar).unwrap().is_some());
+
+    let resealed = jar.find("session.0").unwrap().value().to_string();
+    assert!(SessionCodec::new(vec![aead::decode_key(&new).unwrap()]).unwrap().is_current(&resealed));
+}
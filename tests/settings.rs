@@ -0,0 +1,180 @@
+extern crate example_application;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use example_application::controllers::diagnostics::DiagnosticsAccess;
+use example_application::oauth::settings::{self, Profile, Source, SettingsError};
+
+const VALID: &'static str = r#"{
+    "public_key_path": "./priv/public-key.json",
+    "secret_key_path": "./priv/secret-key.json",
+    "client_id": "123123123",
+    "client_secret": "123123123",
+    "redirect_uri": "https://example.com/",
+    "oauth_uri": "https://oauth.example.com/",
+    "content_uri": "https://content.example.com/",
+    "profile_uri": "https://profile.example.com/",
+    "flow_ttl": 300,
+    "return_to_allowlist": ["/", "/iframe"]
+}"#;
+
+fn problems(json: &str) -> Vec<String> {
+    match settings::load(&Source::from_json(json).unwrap()) {
+        Err(SettingsError::Invalid(problems)) => problems,
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => Vec::new(),
+    }
+}
+
+#[test]
+fn loads_a_config_file() {
+    let settings = settings::load(&Source::from_json(VALID).unwrap()).unwrap();
+
+    assert_eq!(settings.client_id, "123123123");
+    assert_eq!(settings.flow_ttl, 300);
+    assert_eq!(settings.return_to_allowlist, vec!["/".to_string(), "/iframe".to_string()]);
+    assert_eq!(settings.endpoints.token, "https://oauth.example.com/token");
+}
+
+#[test]
+fn reports_every_missing_setting_at_once() {
+    let problems = problems("{}");
+
+    assert!(problems.contains(&"CLIENT_ID required".to_string()));
+    assert!(problems.contains(&"CLIENT_SECRET required".to_string()));
+    assert!(problems.contains(&"REDIRECT_URI required".to_string()));
+}
+
+#[test]
+fn validate_reports_bad_urls_empty_client_id_and_unreadable_keys() {
+    let json = VALID
+        .replace("\"123123123\",\n    \"client_secret\"", "\"\",\n    \"client_secret\"")
+        .replace("https://oauth.example.com/", "oauth.example.com")
+        .replace("./priv/secret-key.json", "./priv/missing.json");
+    let problems = problems(&json);
+
+    assert_eq!(problems.len(), 7);
+    assert!(problems.iter().any(|problem| problem.starts_with("CLIENT_ID")));
+    assert!(problems.iter().any(|problem| problem.starts_with("OAUTH_URI")));
+    assert!(problems.iter().any(|problem| problem.starts_with("SECRET_KEY_PATH")));
+}
+
+#[test]
+fn production_requires_an_https_redirect_uri() {
+    let json = VALID
+        .replace("\"redirect_uri\": \"https://", "\"redirect_uri\": \"http://")
+        .replace("\"flow_ttl\"", "\"app_profile\": \"production\",\n    \"flow_ttl\"");
+
+    assert_eq!(problems(&json), vec!["REDIRECT_URI must use https in production".to_string()]);
+    assert!(problems(&VALID.replace("\"redirect_uri\": \"https://", "\"redirect_uri\": \"http://")).is_empty());
+}
+
+#[test]
+fn profiles_supply_defaults_which_the_config_overrides() {
+    let json = r#"{
+        "app_profile": "stage",
+        "client_id": "123123123",
+        "client_secret": "123123123",
+        "redirect_uri": "https://example.com/",
+        "public_key_path": "./priv/public-key.json",
+        "secret_key_path": "./priv/secret-key.json",
+        "profile_uri": "https://profile.example.com/"
+    }"#;
+    let settings = settings::load(&Source::from_json(json).unwrap()).unwrap();
+
+    assert_eq!(settings.profile, Profile::Staging);
+    assert_eq!(settings.oauth_uri, "https://oauth.stage.mozaws.net/v1");
+    assert_eq!(settings.profile_uri, "https://profile.example.com/");
+    assert!(settings.cookie.secure);
+}
+
+#[test]
+fn production_forces_secure_cookies() {
+    let json = VALID.replace("\"flow_ttl\"", "\"app_profile\": \"production\",\n    \"cookie_secure\": false,\n    \"flow_ttl\"");
+    let settings = settings::load(&Source::from_json(&json).unwrap()).unwrap();
+
+    assert!(settings.is_production());
+    assert!(settings.cookie.secure);
+}
+
+#[test]
+fn diagnostics_answer_only_the_configured_token() {
+    let closed = settings::load(&Source::from_json(VALID).unwrap()).unwrap();
+    let json = VALID.replace(r#""flow_ttl": 300,"#, r#""flow_ttl": 300, "diagnostics_token": "s3cret","#);
+    let open = settings::load(&Source::from_json(&json).unwrap()).unwrap();
+
+    assert!(!DiagnosticsAccess::allows(&closed, Some("Bearer ")));
+    assert!(!DiagnosticsAccess::allows(&open, None));
+    assert!(!DiagnosticsAccess::allows(&open, Some("Bearer s3cre")));
+    assert!(!DiagnosticsAccess::allows(&open, Some("s3cret")));
+    assert!(DiagnosticsAccess::allows(&open, Some("Bearer s3cret")));
+}
+
+#[test]
+fn rejects_unknown_profiles() {
+    assert!(settings::load(&Source::from_json(r#"{"app_profile": "qa"}"#).unwrap()).is_err());
+}
+
+#[test]
+fn client_secret_may_come_from_a_file() {
+    let path = env::temp_dir().join("client-secret");
+    File::create(&path).unwrap().write_all(b"from-a-file\n").unwrap();
+
+    let json = VALID.replace("\"client_secret\": \"123123123\"",
+                             &format!("\"client_secret\": \"file:{}\"", path.to_str().unwrap()));
+    let settings = settings::load(&Source::from_json(&json).unwrap()).unwrap();
+
+    assert_eq!(settings.client_secret.expose(), "from-a-file");
+    assert_eq!(format!("{:?}", settings.client_secret), "Secret([redacted])");
+}
+
+#[test]
+fn reports_which_indirection_failed() {
+    let json = VALID.replace("\"client_secret\": \"123123123\"",
+                             "\"client_secret\": \"env:EXAMPLE_APPLICATION_MISSING_SECRET\"");
+    let problems = problems(&json);
+
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("CLIENT_SECRET: env:EXAMPLE_APPLICATION_MISSING_SECRET"));
+}
+
+#[test]
+fn a_shared_session_store_needs_a_session_secret() {
+    let json = VALID.replace("\"flow_ttl\"", "\"session_store\": \"file\",\n    \"session_store_path\": \"./sessions.json\",\n    \"flow_ttl\"");
+
+    assert_eq!(problems(&json), vec!["SESSION_SECRET required when SESSION_STORE=file".to_string()]);
+    assert!(problems(&json.replace("\"flow_ttl\"", "\"session_secret\": \"s3cret\",\n    \"flow_ttl\"")).is_empty());
+}
+
+#[test]
+fn cookie_sessions_need_valid_keys() {
+    let json = VALID.replace("\"flow_ttl\"", "\"session_backend\": \"cookie\",\n    \"flow_ttl\"");
+
+    assert_eq!(problems(&json), vec!["SESSION_KEYS required when SESSION_BACKEND=cookie".to_string()]);
+    assert_eq!(problems(&json.replace("\"flow_ttl\"", "\"session_keys\": \"c2hvcnQ\",\n    \"flow_ttl\"")),
+               vec!["SESSION_KEYS entry 1 must be a base64url encoded 256-bit key".to_string()]);
+}
+
+#[test]
+fn provider_calls_need_a_timeout_and_a_retry_count() {
+    let settings = settings::load(&Source::from_json(VALID).unwrap()).unwrap();
+    assert_eq!(settings.provider_timeout, 10);
+    assert_eq!(settings.provider_retries, 2);
+
+    let json = VALID.replace("\"flow_ttl\"", "\"provider_retries\": \"some\",\n    \"flow_ttl\"");
+    assert_eq!(problems(&json), vec!["PROVIDER_RETRIES must be a number of retries, not \"some\"".to_string()]);
+
+    let json = VALID.replace("\"flow_ttl\"", "\"provider_timeout\": 0,\n    \"flow_ttl\"");
+    assert_eq!(problems(&json), vec!["PROVIDER_TIMEOUT must be greater than zero".to_string()]);
+}
+
+#[test]
+fn dotenv_lines_are_assignments() {
+    assert_eq!(settings::parse_dotenv_line("CLIENT_ID=123"), Some(("CLIENT_ID".to_string(), "123".to_string())));
+    assert_eq!(settings::parse_dotenv_line("export FLOW_TTL = 600 "), Some(("FLOW_TTL".to_string(), "600".to_string())));
+    assert_eq!(settings::parse_dotenv_line("RETURN_TO_ALLOWLIST=/,/iframe?a=b"), Some(("RETURN_TO_ALLOWLIST".to_string(), "/,/iframe?a=b".to_string())));
+    assert_eq!(settings::parse_dotenv_line("# OIDC_ISSUER=https://accounts.example.com"), None);
+    assert_eq!(settings::parse_dotenv_line(""), None);
+    assert_eq!(settings::parse_dotenv_line("garbage"), None);
+}